@@ -1,25 +1,102 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
 use chrono::DateTime;
 use chrono_tz::Tz;
 
-use crate::{
-    model::{trade::load_trades, wallet::Wallet},
+use rusty_trades::{
+    model::{
+        chart::WalletHistory,
+        duplicates::{find_duplicates, DuplicatePolicy},
+        event::{load_events_from, Event},
+        html::write_html_report,
+        json::WalletJson,
+        ledger::LedgerFormat,
+        period::PeriodScheme,
+        price_store::{PriceLookup, PriceStore},
+        render::{
+            render, render_sections, ForksReport, FxGainsReport, GainsReport, HoldingsReport,
+            HoldingsValueReport, LongTermCalendarReport, OutputFormat, Report, Section,
+            SellTradesReport, StablecoinPoliciesReport, TaxLossHarvestingReport, TradesReport,
+            UnrealizedGainsReport, WhatIfReport,
+        },
+        store::Store,
+        trade::generate_random_consistent_trades,
+        usd_trade::UsdTradesFormat,
+        validation::validate_events,
+        wallet::{LotMethod, Wallet, WalletConfig},
+        what_if::{WhatIfResult, WhatIfSale},
+        Currency,
+    },
     utils::time_utils::{end_of_the_day, start_of_the_day},
 };
 
+/// Part of the report to produce, `Report` being all of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Report,
+    Trades,
+    UsdExport,
+    Sells,
+    Gains,
+    Holdings,
+    Html,
+    Charts,
+    Import,
+    Validate,
+    Generate,
+}
+
 pub struct Config {
-    pub input_file: String,
+    pub command: Command,
+    /// Input files, directories and glob patterns, merged in chronological order.
+    pub input_files: Vec<String>,
+    /// Database to read events from instead of the input files, and to import into.
+    pub db_file: Option<String>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub aliases: HashMap<Currency, Currency>,
+    pub wallet_config: WalletConfig,
+    /// Timezone that datetimes without offset are parsed in and all datetimes are reported in.
+    pub timezone: Tz,
     pub dt_from: Option<DateTime<Tz>>,
     pub dt_to: Option<DateTime<Tz>>,
+    pub usd_trades_file: Option<String>,
+    pub usd_trades_format: UsdTradesFormat,
+    pub json_file: Option<String>,
+    pub ledger_file: Option<String>,
+    pub ledger_format: LedgerFormat,
+    pub price_files: Vec<String>,
+    pub price_lookup: PriceLookup,
+    pub dt_valuation: Option<DateTime<Tz>>,
+    pub price_overrides: HashMap<Currency, f64>,
+    pub harvest_losses: bool,
+    pub what_if_sale: Option<String>,
+    pub dt_what_if: Option<DateTime<Tz>>,
+    pub lot_method: LotMethod,
+    pub long_term_calendar: bool,
+    pub period_scheme: PeriodScheme,
+    pub brief: bool,
+    pub generate_count: usize,
+    pub output_file: Option<String>,
+    pub output_dir: Option<String>,
+    /// Format of the reports of a subcommand.
+    pub format: OutputFormat,
 }
 
 pub fn run(config: Config) {
-    let mut wallet = Wallet::default();
-
-    let trades = load_trades(&config.input_file);
-    for trade in trades {
-        wallet.add_trade(trade);
+    match config.command {
+        Command::Validate => return validate(&config),
+        Command::Generate => return generate(&config),
+        Command::Import => return import(&config),
+        _ => {}
     }
 
+    let price_store = load_price_store(&config);
+    let wallet = build_wallet(&config, load_events_with(&config, &price_store));
+
     let dt_from = config
         .dt_from
         .or_else(|| Some(start_of_the_day(&wallet.trades.first().unwrap().datetime)))
@@ -29,9 +106,441 @@ pub fn run(config: Config) {
         .or_else(|| Some(end_of_the_day(&wallet.trades.last().unwrap().datetime)))
         .unwrap();
 
-    wallet.print_trades(true, &dt_from, &dt_to);
-    wallet.print_usd_trades(&dt_from, &dt_to);
-    wallet.print_sell_trades(true, &dt_from, &dt_to);
-    wallet.print_proceeds(&dt_from, &dt_to);
-    wallet.print_holdings();
+    match config.command {
+        Command::Report => {
+            wallet.print_trades(true, &dt_from, &dt_to);
+            print_usd_trades(&config, &wallet, &dt_from, &dt_to);
+            wallet.print_sell_trades(true, &dt_from, &dt_to);
+            print_sections(&gains_sections(&wallet, &config, &dt_from, &dt_to));
+            let what_if = simulate_what_if(&config, &wallet, &dt_to);
+            let mut sections = holdings_sections(&config, &wallet, &price_store, &dt_to);
+            sections.extend(what_if_section(&what_if));
+            print_sections(&sections);
+            export_wallet(&config, &wallet);
+        }
+        Command::Trades => {
+            let report = TradesReport {
+                trades: wallet.trades_within(&dt_from, &dt_to),
+                print_notes: !config.brief,
+            };
+            write_report(&config, &report);
+            export_wallet(&config, &wallet);
+        }
+        Command::UsdExport => print_usd_trades(&config, &wallet, &dt_from, &dt_to),
+        Command::Sells => {
+            let report = SellTradesReport {
+                sell_trades: wallet.sell_trade_details(&dt_from, &dt_to),
+                full_info: !config.brief,
+            };
+            write_report(&config, &report);
+        }
+        Command::Gains => {
+            let what_if = simulate_what_if(&config, &wallet, &dt_to);
+            let mut sections = gains_sections(&wallet, &config, &dt_from, &dt_to);
+            sections.extend(what_if_section(&what_if));
+            write_sections(&config, &sections);
+        }
+        Command::Holdings => {
+            let sections = holdings_sections(&config, &wallet, &price_store, &dt_to);
+            write_sections(&config, &sections);
+        }
+        Command::Html => {
+            let writer = create_output(&config);
+            or_exit(
+                write_html_report(writer, &wallet, &dt_from, &dt_to),
+                output_name(&config),
+            );
+        }
+        Command::Charts => {
+            let dir_name = config.output_dir.as_deref().unwrap_or(".");
+            let dir = Path::new(dir_name);
+            or_exit(fs::create_dir_all(dir), dir_name);
+            or_exit(
+                WalletHistory::new(&wallet, &price_store, &dt_from, &dt_to).write_charts(dir),
+                dir_name,
+            );
+        }
+        Command::Validate | Command::Generate | Command::Import => unreachable!(),
+    }
+}
+
+fn load_price_store(config: &Config) -> PriceStore {
+    let mut price_store = PriceStore::load(
+        &config.price_files,
+        config.price_lookup,
+        &config.wallet_config.base_currency,
+        &config.timezone,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("Cannot load prices: {}", err);
+        std::process::exit(1);
+    });
+    for (currency, price) in &config.price_overrides {
+        price_store.set_override(currency.clone(), *price);
+    }
+    price_store
+}
+
+/// Loads events of the input files, exiting with an error code if one is invalid.
+fn load_input_events(config: &Config) -> Vec<Event> {
+    match load_events_from(&config.input_files, &config.timezone) {
+        Ok(events) => events,
+        Err(err) => {
+            eprintln!("Cannot load events: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads events of the database, or else of the input files, with aliases applied, duplicates
+/// handled and missing prices filled.
+fn load_events_with(config: &Config, price_store: &PriceStore) -> Vec<Event> {
+    let mut events = match &config.db_file {
+        Some(filename) => Store::open(filename)
+            .and_then(|store| store.load_events(&config.timezone))
+            .unwrap_or_else(|err| {
+                eprintln!("Cannot load events: {}", err);
+                std::process::exit(1);
+            }),
+        None => load_input_events(config),
+    };
+    for event in &mut events {
+        event.apply_aliases(&config.aliases);
+    }
+    let mut events = handle_duplicates(config, events, 0);
+    for event in &mut events {
+        if let Err(err) = price_store.fill_missing_prices(event) {
+            eprintln!("Cannot load events: {}", err);
+            std::process::exit(1);
+        }
+    }
+    events
+}
+
+/// Adds the events to a new wallet, exiting with an error code if they are not in chronological
+/// order.
+fn build_wallet(config: &Config, events: Vec<Event>) -> Wallet {
+    let mut wallet = Wallet::new(config.wallet_config.clone());
+    for event in events {
+        if let Err(err) = wallet.add_event(event) {
+            eprintln!("Cannot load events: {}", err);
+            std::process::exit(1);
+        }
+    }
+    wallet
+}
+
+/// Reports duplicate trades and applies the duplicate policy, exiting with an error code if it
+/// rejects them. Duplicates among the first `known_count` events, already accepted before, are
+/// left as they are.
+fn handle_duplicates(config: &Config, events: Vec<Event>, known_count: usize) -> Vec<Event> {
+    let duplicates: Vec<_> = find_duplicates(&events)
+        .into_iter()
+        .filter(|duplicate| duplicate.event_no > known_count)
+        .collect();
+    for duplicate in &duplicates {
+        eprintln!("Duplicate trade: {}", duplicate);
+    }
+    match config.duplicate_policy.apply(events, &duplicates) {
+        Some(events) => events,
+        None => {
+            eprintln!("{} duplicate trades rejected", duplicates.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the value of the result of writing the output, or exits with an error code.
+fn or_exit<T, E: fmt::Display>(result: Result<T, E>, output_name: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Cannot write {}: {}", output_name, err);
+        std::process::exit(1);
+    })
+}
+
+fn output_name(config: &Config) -> &str {
+    config.output_file.as_deref().unwrap_or("stdout")
+}
+
+/// Creates the output file, or else writes to stdout.
+fn create_output(config: &Config) -> Box<dyn Write> {
+    match &config.output_file {
+        Some(filename) => Box::new(BufWriter::new(or_exit(File::create(filename), filename))),
+        None => Box::new(io::stdout()),
+    }
+}
+
+/// Writes the report in the configured format to the output file or stdout.
+fn write_report(config: &Config, report: &dyn Report) {
+    let mut writer = create_output(config);
+    or_exit(
+        render(report, config.format, &mut writer).and_then(|_| writer.flush()),
+        output_name(config),
+    );
+}
+
+/// Writes the reports in the configured format to the output file or stdout.
+fn write_sections(config: &Config, sections: &[Section]) {
+    let mut writer = create_output(config);
+    or_exit(
+        render_sections(sections, config.format, &mut writer).and_then(|_| writer.flush()),
+        output_name(config),
+    );
+}
+
+fn print_sections(sections: &[Section]) {
+    or_exit(
+        render_sections(sections, OutputFormat::Text, &mut io::stdout()),
+        "stdout",
+    );
+}
+
+fn print_usd_trades(
+    config: &Config,
+    wallet: &Wallet,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) {
+    match &config.usd_trades_file {
+        Some(filename) => or_exit(
+            wallet.export_usd_trades(filename, config.usd_trades_format, dt_from, dt_to),
+            filename,
+        ),
+        None => wallet.print_usd_trades(config.usd_trades_format, dt_from, dt_to),
+    }
+}
+
+/// Gains per period, followed by currency gains, forks and stablecoin policies if relevant.
+fn gains_sections<'a>(
+    wallet: &'a Wallet,
+    config: &Config,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) -> Vec<Section<'a>> {
+    let mut sections = vec![Section {
+        key: "gains",
+        title: "Gains",
+        report: Box::new(GainsReport {
+            periods: wallet.gains_per_period(dt_from, dt_to, config.period_scheme),
+        }),
+    }];
+    if wallet.config().track_fx_gains {
+        sections.push(Section {
+            key: "fx_gains",
+            title: "Currency gains",
+            report: Box::new(FxGainsReport {
+                fx_gains: wallet.fx_gains(dt_from, dt_to),
+            }),
+        });
+    }
+    if !wallet.forks().is_empty() {
+        sections.push(Section {
+            key: "forks",
+            title: "Forks",
+            report: Box::new(ForksReport {
+                forks: wallet.fork_incomes(dt_from, dt_to),
+            }),
+        });
+    }
+    if !wallet.config().stablecoins.is_empty() {
+        sections.push(Section {
+            key: "stablecoin_policies",
+            title: "Stablecoin policies",
+            report: Box::new(StablecoinPoliciesReport {
+                comparison: wallet.stablecoin_comparison(dt_from, dt_to),
+            }),
+        });
+    }
+    sections
+}
+
+/// Held lots, followed by their value, unrealized gains, losses to harvest and long-term
+/// calendar at the valuation time if requested and prices are known.
+fn holdings_sections(
+    config: &Config,
+    wallet: &Wallet,
+    price_store: &PriceStore,
+    dt_to: &DateTime<Tz>,
+) -> Vec<Section<'static>> {
+    let mut sections = vec![Section {
+        key: "holdings",
+        title: "Holdings",
+        report: Box::new(HoldingsReport {
+            holdings: wallet.holdings_summary(),
+        }),
+    }];
+    let dt = config.dt_valuation.unwrap_or(*dt_to);
+    let wallet_at_valuation = wallet.at(&dt);
+    if !price_store.is_empty() {
+        sections.push(Section {
+            key: "holdings_value",
+            title: "Holdings value",
+            report: Box::new(HoldingsValueReport {
+                dt,
+                values: wallet_at_valuation.holdings_value(price_store, &dt),
+            }),
+        });
+        sections.push(Section {
+            key: "unrealized_gains",
+            title: "Unrealized gains",
+            report: Box::new(UnrealizedGainsReport {
+                dt,
+                currencies: wallet_at_valuation.unrealized_gains(price_store, &dt),
+            }),
+        });
+        if config.harvest_losses {
+            sections.push(Section {
+                key: "tax_loss_harvesting",
+                title: "Tax-loss harvesting",
+                report: Box::new(TaxLossHarvestingReport {
+                    dt,
+                    harvesting: wallet_at_valuation.tax_loss_harvesting(price_store, &dt),
+                }),
+            });
+        }
+    }
+    if config.long_term_calendar {
+        sections.push(Section {
+            key: "long_term_calendar",
+            title: "Long-term calendar",
+            report: Box::new(LongTermCalendarReport {
+                dt,
+                lots: wallet_at_valuation.long_term_calendar(price_store, &dt),
+            }),
+        });
+    }
+    sections
+}
+
+/// Simulates the configured what-if sale, exiting with an error code if it is invalid.
+fn simulate_what_if(
+    config: &Config,
+    wallet: &Wallet,
+    dt_to: &DateTime<Tz>,
+) -> Option<WhatIfResult> {
+    let sale = config.what_if_sale.as_ref()?;
+    let dt_what_if = config.dt_what_if.unwrap_or(*dt_to);
+    let result = WhatIfSale::parse(sale, dt_what_if, config.lot_method)
+        .and_then(|sale| wallet.simulate_sale(&sale));
+    match result {
+        Ok(result) => Some(result),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn what_if_section(what_if: &Option<WhatIfResult>) -> Option<Section<'_>> {
+    what_if.as_ref().map(|result| Section {
+        key: "what_if",
+        title: "What-if sale",
+        report: Box::new(WhatIfReport { result }),
+    })
+}
+
+fn export_wallet(config: &Config, wallet: &Wallet) {
+    if let Some(filename) = &config.json_file {
+        or_exit(WalletJson::new(wallet).write(filename), filename);
+    }
+    if let Some(filename) = &config.ledger_file {
+        or_exit(
+            wallet.export_ledger(filename, config.ledger_format),
+            filename,
+        );
+    }
+}
+
+/// Adds events of the input files to the database. The duplicate policy applies to the new events,
+/// checked against the stored ones too, and nothing is stored unless a wallet can be built from
+/// all of them.
+fn import(config: &Config) {
+    let filename = config.db_file.as_deref().unwrap_or_else(|| {
+        eprintln!("Cannot import: no database file given");
+        std::process::exit(1);
+    });
+    let mut store = Store::open(filename).unwrap_or_else(|err| {
+        eprintln!("Cannot open database: {}", err);
+        std::process::exit(1);
+    });
+    let mut events = store.load_events(&config.timezone).unwrap_or_else(|err| {
+        eprintln!("Cannot load events: {}", err);
+        std::process::exit(1);
+    });
+    let stored_count = events.len();
+    let stored_notes: Vec<Vec<String>> = events.iter().map(|e| e.notes().to_vec()).collect();
+    let fingerprints: HashSet<String> = events.iter().map(|e| e.fingerprint()).collect();
+    let mut new_events = load_input_events(config);
+    for event in &mut new_events {
+        event.apply_aliases(&config.aliases);
+    }
+    // Events imported before are skipped rather than reported as duplicates.
+    let new_count = new_events.len();
+    new_events.retain(|event| !fingerprints.contains(&event.fingerprint()));
+    let already_stored = new_count - new_events.len();
+    events.extend(new_events);
+
+    let mut events = handle_duplicates(config, events, stored_count);
+    let new_events = events.split_off(stored_count);
+    // Notes of duplicates merged into stored events.
+    let updated: Vec<Event> = events
+        .iter()
+        .zip(&stored_notes)
+        .filter(|(event, notes)| event.notes() != notes.as_slice())
+        .map(|(event, _)| event.clone())
+        .collect();
+
+    // Events in the order they are loaded from the database after the import, stored ones first
+    // at the same millisecond.
+    events.extend(new_events.iter().cloned());
+    events.sort_by_key(|event| event.datetime().timestamp_millis());
+    let price_store = load_price_store(config);
+    for event in &mut events {
+        if let Err(err) = price_store.fill_missing_prices(event) {
+            eprintln!("Cannot load events: {}", err);
+            std::process::exit(1);
+        }
+    }
+    build_wallet(config, events);
+
+    let mut summary = store.import(&updated, &new_events).unwrap_or_else(|err| {
+        eprintln!("Cannot import: {}", err);
+        std::process::exit(1);
+    });
+    summary.skipped += already_stored;
+    println!(
+        "{} events imported, {} already stored",
+        summary.added, summary.skipped
+    );
+}
+
+/// Prints problems of the input files and exits with an error code if there are any.
+fn validate(config: &Config) {
+    let price_store = load_price_store(config);
+    let mut events = load_input_events(config);
+    for event in &mut events {
+        event.apply_aliases(&config.aliases);
+    }
+    let problems = validate_events(&events, &config.wallet_config, &price_store);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        println!("{} problems in {} events", problems.len(), events.len());
+        std::process::exit(1);
+    }
+    println!("{} events OK", events.len());
+}
+
+/// Writes random but consistent trades within the time boundaries as an input file.
+fn generate(config: &Config) {
+    let dt_from = config.dt_from.expect("Left time boundary is required");
+    let dt_to = config.dt_to.expect("Right time boundary is required");
+    let trades = generate_random_consistent_trades(config.generate_count, &dt_from, &dt_to);
+    let mut writer = create_output(config);
+    let result = trades
+        .iter()
+        .try_for_each(|trade| writeln!(writer, "{}", trade))
+        .and_then(|_| writer.flush());
+    or_exit(result, output_name(config));
 }
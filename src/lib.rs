@@ -0,0 +1,38 @@
+//! Capital gains of crypto currency trades: FIFO matching of sells to lots, cost basis, proceeds,
+//! short-term and long-term gains, holdings and exports for tax software.
+//!
+//! Events are loaded from a text or JSON file with [`load_events`] and added in chronological
+//! order to a [`Wallet`], which keeps the results as data:
+//!
+//! ```no_run
+//! use rusty_trades::{load_events, Wallet, WalletConfig};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut wallet = Wallet::new(WalletConfig::default());
+//! for event in load_events("input.txt", &chrono_tz::UTC)? {
+//!     wallet.add_event(event)?;
+//! }
+//! for sell_trade in wallet.sell_trades() {
+//!     println!("{} {} gain={}", sell_trade.volume, sell_trade.currency, sell_trade.gain());
+//! }
+//! for (currency, lots) in wallet.holdings() {
+//!     let volume: f64 = lots.iter().map(|lot| lot.volume).sum();
+//!     println!("{} {}", volume, currency);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Datetimes without a UTC offset are parsed in the timezone passed to the loaders, which the
+//! loaded events are also reported in.
+
+pub mod model;
+pub mod utils;
+
+pub use crate::model::{
+    event::{load_events, load_events_from, Event, Fork, Migration},
+    sell_trade::SellTrade,
+    trade::Trade,
+    wallet::{HoldingsItem, Wallet, WalletConfig},
+    Currency,
+};
@@ -1,41 +1,219 @@
 mod app;
-mod model;
-mod utils;
 
 use std::env;
 
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, SubCommand};
 use dotenv::dotenv;
 
-use crate::{
-    app::{run, Config},
-    utils::time_utils::datetime_from_str,
+use rusty_trades::{
+    model::{
+        duplicates::DuplicatePolicy,
+        ledger::LedgerFormat,
+        period::PeriodScheme,
+        price_store::PriceLookup,
+        render::OutputFormat,
+        usd_trade::UsdTradesFormat,
+        wallet::{ForkBasis, LotMethod, StablecoinPolicy, WalletConfig},
+        DEFAULT_BASE_CURRENCY,
+    },
+    utils::time_utils::{datetime_from_str, DEFAULT_APP_TZ},
 };
 
+use crate::app::{run, Command, Config};
+
+const CMD_TRADES: &str = "trades";
+const CMD_USD_EXPORT: &str = "usd-export";
+const CMD_SELLS: &str = "sells";
+const CMD_GAINS: &str = "gains";
+const CMD_HOLDINGS: &str = "holdings";
+const CMD_VALIDATE: &str = "validate";
+const CMD_GENERATE: &str = "generate";
+const CMD_HTML: &str = "html";
+const CMD_CHARTS: &str = "charts";
+const CMD_IMPORT: &str = "import";
+
 const OPT_INPUT_FILE: &str = "file";
+const OPT_ALIASES: &str = "aliases";
+const OPT_BASE_CURRENCY: &str = "base-currency";
+const OPT_CASH_CURRENCIES: &str = "cash-currencies";
+const OPT_TRACK_FX_GAINS: &str = "track-fx-gains";
+const OPT_FX_EXEMPTION_THRESHOLD: &str = "fx-exemption-threshold";
+const OPT_STABLECOINS: &str = "stablecoins";
+const OPT_FORK_BASIS: &str = "fork-basis";
+const OPT_TIMEZONE: &str = "timezone";
 const OPT_TIME_FROM: &str = "time-from";
 const OPT_TIME_TO: &str = "time-to";
+const OPT_PERIODS: &str = "periods";
+const OPT_USD_TRADES_FILE: &str = "usd-trades-file";
+const OPT_USD_TRADES_FORMAT: &str = "usd-trades-format";
+const OPT_JSON_FILE: &str = "json-file";
+const OPT_LEDGER_FILE: &str = "ledger-file";
+const OPT_LEDGER_FORMAT: &str = "ledger-format";
+const OPT_PRICE_FILE: &str = "price-file";
+const OPT_PRICE_LOOKUP: &str = "price-lookup";
+const OPT_PRICES: &str = "prices";
+const OPT_HARVEST_LOSSES: &str = "harvest-losses";
+const OPT_WHAT_IF: &str = "what-if";
+const OPT_WHAT_IF_TIME: &str = "what-if-time";
+const OPT_LOT_METHOD: &str = "lot-method";
+const OPT_LONG_TERM_CALENDAR: &str = "long-term-calendar";
+const OPT_BRIEF: &str = "brief";
+const OPT_COUNT: &str = "count";
+const OPT_OUTPUT_FILE: &str = "output";
+const OPT_FORMAT: &str = "format";
+const OPT_OUTPUT_DIR: &str = "output-dir";
+const OPT_DB_FILE: &str = "db";
+const OPT_DUPLICATES: &str = "duplicates";
+const OPT_VALUATION_TIME: &str = "valuation-time";
 
 const ENV_INPUT_FILE: &str = "FILE";
+const ENV_ALIASES: &str = "ALIASES";
+const ENV_BASE_CURRENCY: &str = "BASE_CURRENCY";
+const ENV_CASH_CURRENCIES: &str = "CASH_CURRENCIES";
+const ENV_TRACK_FX_GAINS: &str = "TRACK_FX_GAINS";
+const ENV_FX_EXEMPTION_THRESHOLD: &str = "FX_EXEMPTION_THRESHOLD";
+const ENV_STABLECOINS: &str = "STABLECOINS";
+const ENV_FORK_BASIS: &str = "FORK_BASIS";
+const ENV_TIMEZONE: &str = "TIMEZONE";
 const ENV_TIME_FROM: &str = "TIME_FROM";
 const ENV_TIME_TO: &str = "TIME_TO";
+const ENV_PERIODS: &str = "PERIODS";
+const ENV_USD_TRADES_FILE: &str = "USD_TRADES_FILE";
+const ENV_USD_TRADES_FORMAT: &str = "USD_TRADES_FORMAT";
+const ENV_JSON_FILE: &str = "JSON_FILE";
+const ENV_LEDGER_FILE: &str = "LEDGER_FILE";
+const ENV_LEDGER_FORMAT: &str = "LEDGER_FORMAT";
+const ENV_PRICE_FILES: &str = "PRICE_FILES";
+const ENV_PRICE_LOOKUP: &str = "PRICE_LOOKUP";
+const ENV_PRICES: &str = "PRICES";
+const ENV_HARVEST_LOSSES: &str = "HARVEST_LOSSES";
+const ENV_WHAT_IF: &str = "WHAT_IF";
+const ENV_WHAT_IF_TIME: &str = "WHAT_IF_TIME";
+const ENV_LOT_METHOD: &str = "LOT_METHOD";
+const ENV_LONG_TERM_CALENDAR: &str = "LONG_TERM_CALENDAR";
+const ENV_COUNT: &str = "COUNT";
+const ENV_OUTPUT_FILE: &str = "OUTPUT_FILE";
+const ENV_FORMAT: &str = "FORMAT";
+const ENV_OUTPUT_DIR: &str = "OUTPUT_DIR";
+const ENV_DB_FILE: &str = "DB_FILE";
+const ENV_DUPLICATES: &str = "DUPLICATES";
+const ENV_VALUATION_TIME: &str = "VALUATION_TIME";
 
 const DEFAULT_INPUT_FILE: &str = "input.txt";
+const DEFAULT_COUNT: usize = 100;
 
 fn main() {
     dotenv().ok();
 
-    let matches = App::new(crate_name!())
+    let format_arg = Arg::with_name(OPT_FORMAT)
+        .long(OPT_FORMAT)
+        .value_name(ENV_FORMAT)
+        .help("Format of the report: 'text' (default), 'csv', 'json' or 'markdown'")
+        .takes_value(true);
+    let output_arg = Arg::with_name(OPT_OUTPUT_FILE)
+        .short("o")
+        .long(OPT_OUTPUT_FILE)
+        .value_name(ENV_OUTPUT_FILE)
+        .help("Name of the file to write to instead of stdout")
+        .takes_value(true);
+
+    let app_matches = App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .after_help("Prints all reports when no subcommand is given.")
         .arg(
             Arg::with_name(OPT_INPUT_FILE)
                 .short("f")
                 .long(OPT_INPUT_FILE)
                 .value_name(ENV_INPUT_FILE)
-                .help("Name of the input file containing trades")
-                .takes_value(true),
+                .help("Name of an input file, directory or glob pattern containing trades (can be repeated; env var is comma-separated)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_DUPLICATES)
+                .long(OPT_DUPLICATES)
+                .value_name(ENV_DUPLICATES)
+                .help("What to do with trades duplicating an earlier one: 'keep-all' (default), 'reject', 'keep-first' or 'merge-notes'")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_DB_FILE)
+                .long(OPT_DB_FILE)
+                .value_name(ENV_DB_FILE)
+                .help("Name of the SQLite database to import into, and to read events from instead of the input files")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_ALIASES)
+                .short("a")
+                .long(OPT_ALIASES)
+                .value_name(ENV_ALIASES)
+                .help("Comma-separated currency aliases applied on load (e.g.: 'XBT=BTC,XDG=DOGE')")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_BASE_CURRENCY)
+                .short("b")
+                .long(OPT_BASE_CURRENCY)
+                .value_name(ENV_BASE_CURRENCY)
+                .help("Fiat currency that prices are given in and gains are reported in (default: USD)")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_CASH_CURRENCIES)
+                .long(OPT_CASH_CURRENCIES)
+                .value_name(ENV_CASH_CURRENCIES)
+                .help("Comma-separated fiat currencies other than the base one held as cash (e.g.: 'EUR,GBP')")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_TRACK_FX_GAINS)
+                .long(OPT_TRACK_FX_GAINS)
+                .help("Track lots of foreign cash and report currency gains separately")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_FX_EXEMPTION_THRESHOLD)
+                .long(OPT_FX_EXEMPTION_THRESHOLD)
+                .value_name(ENV_FX_EXEMPTION_THRESHOLD)
+                .help("Currency gains per trade up to this amount are exempt as personal use (default: 0)")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_STABLECOINS)
+                .long(OPT_STABLECOINS)
+                .value_name(ENV_STABLECOINS)
+                .help("Comma-separated stablecoin policies, 'cash' or 'property' (e.g.: 'USDT=cash,USDC=property')")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_FORK_BASIS)
+                .long(OPT_FORK_BASIS)
+                .value_name(ENV_FORK_BASIS)
+                .help("Cost basis of coins received in forks, 'zero' or 'income' at market value (default: 'zero')")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_TIMEZONE)
+                .long(OPT_TIMEZONE)
+                .value_name(ENV_TIMEZONE)
+                .help("Timezone of reports and of day and period boundaries (e.g.: 'US/Pacific', default: UTC)")
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name(OPT_TIME_FROM)
@@ -43,7 +221,8 @@ fn main() {
                 .long(OPT_TIME_FROM)
                 .value_name(ENV_TIME_FROM)
                 .help("Left time boundary for trades analysis (e.g.: '2017-01-01 00:00:00-07:30')")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::with_name(OPT_TIME_TO)
@@ -51,34 +230,490 @@ fn main() {
                 .long(OPT_TIME_TO)
                 .value_name(ENV_TIME_TO)
                 .help("Right time boundary for trades analysis (e.g.: '2017-12-31 23:59:59+00:00')")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_PRICE_FILE)
+                .short("p")
+                .long(OPT_PRICE_FILE)
+                .value_name(ENV_PRICE_FILES)
+                .help("Name of a CSV file with historical prices in the base currency (can be repeated; env var is comma-separated)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_PRICE_LOOKUP)
+                .long(OPT_PRICE_LOOKUP)
+                .value_name(ENV_PRICE_LOOKUP)
+                .help("How historical prices are looked up: 'nearest' (default) or 'close'")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name(OPT_PRICES)
+                .long(OPT_PRICES)
+                .value_name(ENV_PRICES)
+                .help("Comma-separated prices to value holdings with, overriding historical ones (e.g.: 'BTC=40000,ETH=3000')")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_TRADES)
+                .about("Prints trades with notes and exports them to JSON or a journal")
+                .arg(
+                    Arg::with_name(OPT_BRIEF)
+                        .long(OPT_BRIEF)
+                        .help("Omit notes and the trades each sell trade comes from"),
+                )
+                .arg(
+                    Arg::with_name(OPT_JSON_FILE)
+                        .long(OPT_JSON_FILE)
+                        .value_name(ENV_JSON_FILE)
+                        .help("Name of the JSON (or JSON Lines if '.jsonl') file to write trades, sell trades, USD trades and holdings to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_LEDGER_FILE)
+                        .long(OPT_LEDGER_FILE)
+                        .value_name(ENV_LEDGER_FILE)
+                        .help("Name of the plain-text accounting journal file to write all trades to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_LEDGER_FORMAT)
+                        .long(OPT_LEDGER_FORMAT)
+                        .value_name(ENV_LEDGER_FORMAT)
+                        .help("Syntax of the journal file: 'beancount' (default) or 'ledger'")
+                        .takes_value(true),
+                )
+                .arg(format_arg.clone())
+                .arg(output_arg.clone())
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_USD_EXPORT)
+                .about("Exports USD trades as CSV")
+                .arg(
+                    Arg::with_name(OPT_USD_TRADES_FILE)
+                        .long(OPT_USD_TRADES_FILE)
+                        .value_name(ENV_USD_TRADES_FILE)
+                        .help("Name of the CSV file to write USD trades to instead of stdout")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_USD_TRADES_FORMAT)
+                        .long(OPT_USD_TRADES_FORMAT)
+                        .value_name(ENV_USD_TRADES_FORMAT)
+                        .help("Column layout of the USD trades CSV: 'bitcointax' (default), 'koinly' or 'cointracker'")
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_SELLS)
+                .about("Prints sell trades as per Form 8949")
+                .arg(
+                    Arg::with_name(OPT_BRIEF)
+                        .long(OPT_BRIEF)
+                        .help("Omit notes and the trades each sell trade comes from"),
+                )
+                .arg(format_arg.clone())
+                .arg(output_arg.clone())
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_GAINS)
+                .about("Prints realized gains per term and period")
+                .arg(
+                    Arg::with_name(OPT_PERIODS)
+                        .long(OPT_PERIODS)
+                        .value_name(ENV_PERIODS)
+                        .help("Periods to summarize gains for: 'us-estimated' (default), 'quarterly', 'monthly', 'yearly', 'uk', 'au' or 'fiscal:MM-DD'")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_WHAT_IF)
+                        .long(OPT_WHAT_IF)
+                        .value_name(ENV_WHAT_IF)
+                        .help("Hypothetical sale for the base currency to simulate (e.g.: '1.5 BTC @ 40000')")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_WHAT_IF_TIME)
+                        .long(OPT_WHAT_IF_TIME)
+                        .value_name(ENV_WHAT_IF_TIME)
+                        .help("Time of the hypothetical sale (default: right time boundary)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_LOT_METHOD)
+                        .long(OPT_LOT_METHOD)
+                        .value_name(ENV_LOT_METHOD)
+                        .help("Lots sold first by the hypothetical sale: 'fifo' (default), 'lifo' or 'hifo'")
+                        .takes_value(true),
+                )
+                .arg(format_arg.clone())
+                .arg(output_arg.clone())
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_HOLDINGS)
+                .about("Prints held lots, their value and unrealized gains")
+                .arg(
+                    Arg::with_name(OPT_VALUATION_TIME)
+                        .long(OPT_VALUATION_TIME)
+                        .value_name(ENV_VALUATION_TIME)
+                        .help("Time to value holdings at using historical prices (default: right time boundary)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(OPT_HARVEST_LOSSES)
+                        .long(OPT_HARVEST_LOSSES)
+                        .help("Rank lots that would realize losses if sold at valuation time"),
+                )
+                .arg(
+                    Arg::with_name(OPT_LONG_TERM_CALENDAR)
+                        .long(OPT_LONG_TERM_CALENDAR)
+                        .help("List held lots with the date their sale becomes long-term"),
+                )
+                .arg(format_arg)
+                .arg(output_arg.clone())
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_HTML)
+                .about("Writes a self-contained HTML report of trades, gains, holdings and fees within the time boundaries")
+                .arg(output_arg.clone())
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_CHARTS)
+                .about("Writes SVG charts of cost basis, realized gains and, with prices, market value over time")
+                .arg(
+                    Arg::with_name(OPT_OUTPUT_DIR)
+                        .short("d")
+                        .long(OPT_OUTPUT_DIR)
+                        .value_name(ENV_OUTPUT_DIR)
+                        .help("Directory to write the SVG files to (default: current directory)")
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_IMPORT)
+                .about("Adds events of the input files not stored yet to the database given with --db")
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_VALIDATE)
+                .about("Checks the input files for problems without producing reports")
+        )
+        .subcommand(
+            SubCommand::with_name(CMD_GENERATE)
+                .about("Generates an input file with random trades within the time boundaries")
+                .arg(
+                    Arg::with_name(OPT_COUNT)
+                        .short("n")
+                        .long(OPT_COUNT)
+                        .value_name(ENV_COUNT)
+                        .help("Number of trades to generate (default: 100)")
+                        .takes_value(true),
+                )
+                .arg(output_arg)
         )
         .get_matches();
 
-    let input_file = matches
-        .value_of(OPT_INPUT_FILE)
+    let (command, matches) = match app_matches.subcommand() {
+        (CMD_TRADES, Some(m)) => (Command::Trades, m),
+        (CMD_USD_EXPORT, Some(m)) => (Command::UsdExport, m),
+        (CMD_SELLS, Some(m)) => (Command::Sells, m),
+        (CMD_GAINS, Some(m)) => (Command::Gains, m),
+        (CMD_HOLDINGS, Some(m)) => (Command::Holdings, m),
+        (CMD_VALIDATE, Some(m)) => (Command::Validate, m),
+        (CMD_GENERATE, Some(m)) => (Command::Generate, m),
+        (CMD_HTML, Some(m)) => (Command::Html, m),
+        (CMD_CHARTS, Some(m)) => (Command::Charts, m),
+        (CMD_IMPORT, Some(m)) => (Command::Import, m),
+        _ => (Command::Report, &app_matches),
+    };
+
+    let input_files: Vec<String> = matches
+        .values_of(OPT_INPUT_FILE)
+        .map(|values| values.map(|s| s.to_owned()).collect())
+        .or_else(|| {
+            env::var(ENV_INPUT_FILE)
+                .ok()
+                .map(|s| s.split(',').map(|s| s.to_owned()).collect())
+        })
+        .unwrap_or_else(|| vec![DEFAULT_INPUT_FILE.to_owned()]);
+
+    let aliases = matches
+        .value_of(OPT_ALIASES)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_ALIASES).ok())
+        .map(|s| {
+            s.split(',')
+                .map(|item| {
+                    let (alias, currency) = item.split_once('=').unwrap();
+                    (alias.trim().to_owned(), currency.trim().to_owned())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let base_currency = matches
+        .value_of(OPT_BASE_CURRENCY)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_BASE_CURRENCY).ok())
+        .unwrap_or_else(|| DEFAULT_BASE_CURRENCY.to_owned());
+
+    let cash_currencies = matches
+        .value_of(OPT_CASH_CURRENCIES)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_CASH_CURRENCIES).ok())
+        .map(|s| s.split(',').map(|c| c.trim().to_owned()).collect())
+        .unwrap_or_default();
+
+    let track_fx_gains =
+        matches.is_present(OPT_TRACK_FX_GAINS) || env::var(ENV_TRACK_FX_GAINS).is_ok();
+
+    let fx_exemption_threshold = matches
+        .value_of(OPT_FX_EXEMPTION_THRESHOLD)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_FX_EXEMPTION_THRESHOLD).ok())
+        .map(|s| s.parse::<f64>().unwrap())
+        .unwrap_or(0.0);
+
+    let stablecoins = matches
+        .value_of(OPT_STABLECOINS)
         .map(|s| s.to_owned())
-        .or(env::var(ENV_INPUT_FILE).ok())
-        .or_else(|| Some(DEFAULT_INPUT_FILE.to_owned()))
-        .unwrap();
+        .or(env::var(ENV_STABLECOINS).ok())
+        .map(|s| {
+            s.split(',')
+                .map(|item| {
+                    let (currency, policy) = item.split_once('=').unwrap();
+                    let policy = policy.trim().parse::<StablecoinPolicy>().unwrap();
+                    (currency.trim().to_owned(), policy)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let fork_basis = matches
+        .value_of(OPT_FORK_BASIS)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_FORK_BASIS).ok())
+        .map(|s| s.parse::<ForkBasis>().unwrap())
+        .unwrap_or(ForkBasis::Zero);
+
+    let wallet_config = WalletConfig {
+        base_currency,
+        cash_currencies,
+        track_fx_gains,
+        fx_exemption_threshold,
+        stablecoins,
+        fork_basis,
+    };
+
+    let timezone = matches
+        .value_of(OPT_TIMEZONE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_TIMEZONE).ok())
+        .map(|s| s.parse::<Tz>().unwrap())
+        .unwrap_or(DEFAULT_APP_TZ);
 
     let dt_from = matches
         .value_of(OPT_TIME_FROM)
         .map(|s| s.to_owned())
         .or(env::var(ENV_TIME_FROM).ok())
-        .map(|s| datetime_from_str(&s).unwrap());
+        .map(|s| parse_datetime_option(OPT_TIME_FROM, &s, &timezone));
 
     let dt_to = matches
         .value_of(OPT_TIME_TO)
         .map(|s| s.to_owned())
         .or(env::var(ENV_TIME_TO).ok())
-        .map(|s| datetime_from_str(&s).unwrap());
+        .map(|s| parse_datetime_option(OPT_TIME_TO, &s, &timezone));
+
+    let period_scheme = matches
+        .value_of(OPT_PERIODS)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_PERIODS).ok())
+        .map(|s| s.parse::<PeriodScheme>().unwrap())
+        .unwrap_or(PeriodScheme::UsEstimated);
+
+    let usd_trades_file = matches
+        .value_of(OPT_USD_TRADES_FILE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_USD_TRADES_FILE).ok());
+
+    let usd_trades_format = matches
+        .value_of(OPT_USD_TRADES_FORMAT)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_USD_TRADES_FORMAT).ok())
+        .map(|s| s.parse::<UsdTradesFormat>().unwrap())
+        .unwrap_or(UsdTradesFormat::BitcoinTax);
+
+    let json_file = matches
+        .value_of(OPT_JSON_FILE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_JSON_FILE).ok());
+
+    let ledger_file = matches
+        .value_of(OPT_LEDGER_FILE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_LEDGER_FILE).ok());
+
+    let ledger_format = matches
+        .value_of(OPT_LEDGER_FORMAT)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_LEDGER_FORMAT).ok())
+        .map(|s| s.parse::<LedgerFormat>().unwrap())
+        .unwrap_or(LedgerFormat::Beancount);
+
+    let price_files: Vec<String> = matches
+        .values_of(OPT_PRICE_FILE)
+        .map(|values| values.map(|s| s.to_owned()).collect())
+        .or_else(|| {
+            env::var(ENV_PRICE_FILES)
+                .ok()
+                .map(|s| s.split(',').map(|s| s.to_owned()).collect())
+        })
+        .unwrap_or_default();
+
+    let price_lookup = matches
+        .value_of(OPT_PRICE_LOOKUP)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_PRICE_LOOKUP).ok())
+        .map(|s| s.parse::<PriceLookup>().unwrap())
+        .unwrap_or(PriceLookup::Nearest);
+
+    let dt_valuation = matches
+        .value_of(OPT_VALUATION_TIME)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_VALUATION_TIME).ok())
+        .map(|s| parse_datetime_option(OPT_VALUATION_TIME, &s, &timezone));
+
+    let price_overrides = matches
+        .value_of(OPT_PRICES)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_PRICES).ok())
+        .map(|s| {
+            s.split(',')
+                .map(|item| {
+                    let (currency, price) = item.split_once('=').unwrap();
+                    (
+                        currency.trim().to_owned(),
+                        price.trim().parse::<f64>().unwrap(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let harvest_losses =
+        matches.is_present(OPT_HARVEST_LOSSES) || env::var(ENV_HARVEST_LOSSES).is_ok();
+
+    let what_if_sale = matches
+        .value_of(OPT_WHAT_IF)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_WHAT_IF).ok());
+
+    let dt_what_if = matches
+        .value_of(OPT_WHAT_IF_TIME)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_WHAT_IF_TIME).ok())
+        .map(|s| parse_datetime_option(OPT_WHAT_IF_TIME, &s, &timezone));
+
+    let lot_method = matches
+        .value_of(OPT_LOT_METHOD)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_LOT_METHOD).ok())
+        .map(|s| s.parse::<LotMethod>().unwrap())
+        .unwrap_or(LotMethod::Fifo);
+
+    let long_term_calendar =
+        matches.is_present(OPT_LONG_TERM_CALENDAR) || env::var(ENV_LONG_TERM_CALENDAR).is_ok();
+
+    let brief = matches.is_present(OPT_BRIEF);
+
+    let generate_count = matches
+        .value_of(OPT_COUNT)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_COUNT).ok())
+        .map(|s| s.parse::<usize>().unwrap())
+        .unwrap_or(DEFAULT_COUNT);
+
+    let output_file = matches
+        .value_of(OPT_OUTPUT_FILE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_OUTPUT_FILE).ok());
+
+    let db_file = matches
+        .value_of(OPT_DB_FILE)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_DB_FILE).ok());
+
+    if command == Command::Import && db_file.is_none() {
+        clap::Error::with_description(
+            &format!("--{} is required to import", OPT_DB_FILE),
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+
+    let duplicate_policy = matches
+        .value_of(OPT_DUPLICATES)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_DUPLICATES).ok())
+        .map(|s| s.parse::<DuplicatePolicy>().unwrap())
+        .unwrap_or(DuplicatePolicy::KeepAll);
+
+    let output_dir = matches
+        .value_of(OPT_OUTPUT_DIR)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_OUTPUT_DIR).ok());
+
+    let format = matches
+        .value_of(OPT_FORMAT)
+        .map(|s| s.to_owned())
+        .or(env::var(ENV_FORMAT).ok())
+        .map(|s| s.parse::<OutputFormat>().unwrap())
+        .unwrap_or(OutputFormat::Text);
 
     let config = Config {
-        input_file,
+        command,
+        input_files,
+        db_file,
+        duplicate_policy,
+        aliases,
+        wallet_config,
+        timezone,
         dt_from,
         dt_to,
+        usd_trades_file,
+        usd_trades_format,
+        json_file,
+        ledger_file,
+        ledger_format,
+        price_files,
+        price_lookup,
+        dt_valuation,
+        price_overrides,
+        harvest_losses,
+        what_if_sale,
+        dt_what_if,
+        lot_method,
+        long_term_calendar,
+        period_scheme,
+        brief,
+        generate_count,
+        output_file,
+        output_dir,
+        format,
     };
 
     run(config);
 }
+
+/// Parses the datetime of an option, exiting with an error code if it is invalid.
+fn parse_datetime_option(option: &str, s: &str, tz: &Tz) -> DateTime<Tz> {
+    datetime_from_str(s, tz).unwrap_or_else(|err| {
+        eprintln!("Invalid --{}: {}", option, err);
+        std::process::exit(1);
+    })
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone};
+use chrono_tz::Tz;
+
+use crate::{
+    model::{price_store::PriceStore, wallet::Wallet, Currency},
+    utils::time_utils::is_datetime_within_limits,
+};
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 450.0;
+const MARGIN_LEFT: f64 = 90.0;
+const MARGIN_RIGHT: f64 = 140.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+const TICKS: usize = 5;
+const COLORS: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+/// Named values over time, each holding until the next point.
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(DateTime<Tz>, f64)>,
+}
+
+/// Series of the wallet state after every event within the time boundaries.
+pub struct WalletHistory {
+    /// Cost basis of the lots held per currency.
+    pub cost_basis: Vec<Series>,
+    /// Realized gains since the first event.
+    pub realized_gains: Series,
+    /// Market value and cost basis of all lots held, at times when prices of all of them are known.
+    pub market_value: Vec<Series>,
+}
+
+impl WalletHistory {
+    /// Replays the wallet, valuing holdings with historical prices if the price store has any.
+    pub fn new(
+        wallet: &Wallet,
+        price_store: &PriceStore,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+    ) -> Self {
+        let mut cost_basis = BTreeMap::<Currency, Vec<(DateTime<Tz>, f64)>>::new();
+        let mut realized_gains = vec![];
+        let mut market_value = vec![];
+        let mut total_cost_basis = vec![];
+        let mut gains = 0.0;
+        let mut sell_trades_seen = 0;
+
+        wallet.replay_each(|dt, state| {
+            for st in &state.sell_trades()[sell_trades_seen..] {
+                gains += st.gain();
+            }
+            sell_trades_seen = state.sell_trades().len();
+            if !is_datetime_within_limits(dt, dt_from, dt_to) {
+                return;
+            }
+
+            realized_gains.push((*dt, gains));
+            // Currencies sold out drop to zero rather than keep their last cost basis.
+            for points in cost_basis.values_mut() {
+                points.push((*dt, 0.0));
+            }
+            let mut value = Some(0.0);
+            let mut total = 0.0;
+            for (currency, lots) in state.holdings() {
+                let volume: f64 = lots.iter().map(|lot| lot.volume).sum();
+                let basis: f64 = lots.iter().map(|lot| lot.cost_basis).sum();
+                let points = cost_basis.entry(currency.clone()).or_default();
+                match points.last_mut() {
+                    Some(last) if last.0 == *dt => last.1 = basis,
+                    _ => points.push((*dt, basis)),
+                }
+                total += basis;
+                value = value.and_then(|value| {
+                    price_store
+                        .price(currency, dt)
+                        .map(|price| value + volume * price)
+                });
+            }
+            if let Some(value) = value {
+                market_value.push((*dt, value));
+                total_cost_basis.push((*dt, total));
+            }
+        });
+
+        let market_value = if price_store.is_empty() {
+            vec![]
+        } else {
+            vec![
+                Series {
+                    name: "Market value".to_owned(),
+                    points: market_value,
+                },
+                Series {
+                    name: "Cost basis".to_owned(),
+                    points: total_cost_basis,
+                },
+            ]
+        };
+        WalletHistory {
+            cost_basis: cost_basis
+                .into_iter()
+                .map(|(name, points)| Series { name, points })
+                .collect(),
+            realized_gains: Series {
+                name: "Realized gains".to_owned(),
+                points: realized_gains,
+            },
+            market_value,
+        }
+    }
+
+    /// Writes `cost_basis.svg`, `realized_gains.svg` and, with prices, `market_value.svg` to the
+    /// directory.
+    pub fn write_charts(&self, dir: &Path) -> io::Result<()> {
+        let mut charts = vec![
+            (
+                "cost_basis.svg",
+                "Cost basis per currency",
+                &self.cost_basis[..],
+            ),
+            (
+                "realized_gains.svg",
+                "Cumulative realized gains",
+                std::slice::from_ref(&self.realized_gains),
+            ),
+        ];
+        if !self.market_value.is_empty() {
+            charts.push(("market_value.svg", "Market value", &self.market_value[..]));
+        }
+        for (filename, title, series) in charts {
+            let file = File::create(dir.join(filename))?;
+            write_svg_chart(BufWriter::new(file), title, series)?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Writes a standalone SVG step chart of the series with axes and a legend.
+pub fn write_svg_chart<W: Write>(mut writer: W, title: &str, series: &[Series]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"12\">",
+        w = WIDTH,
+        h = HEIGHT
+    )?;
+    writeln!(
+        writer,
+        "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>"
+    )?;
+    writeln!(
+        writer,
+        "<text x=\"{}\" y=\"24\" font-size=\"16\">{}</text>",
+        MARGIN_LEFT,
+        escape(title)
+    )?;
+
+    let points = || series.iter().flat_map(|s| s.points.iter());
+    let (t_min, t_max) = match (
+        points().map(|p| p.0.timestamp()).min(),
+        points().map(|p| p.0.timestamp()).max(),
+    ) {
+        (Some(t_min), Some(t_max)) => (t_min, t_max),
+        _ => {
+            writeln!(
+                writer,
+                "<text x=\"{}\" y=\"{}\">No data</text>",
+                WIDTH / 2.0,
+                HEIGHT / 2.0
+            )?;
+            writeln!(writer, "</svg>")?;
+            return writer.flush();
+        }
+    };
+    let tz = points().next().unwrap().0.timezone();
+    let v_min = points().map(|p| p.1).fold(0.0, f64::min);
+    let v_max = points().map(|p| p.1).fold(0.0, f64::max);
+    let (v_min, v_max) = if v_max > v_min {
+        (v_min, v_max)
+    } else {
+        (v_min, v_min + 1.0)
+    };
+    let t_span = std::cmp::max(t_max - t_min, 1) as f64;
+    let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let x = |t: i64| MARGIN_LEFT + (t - t_min) as f64 / t_span * plot_width;
+    let y = |v: f64| MARGIN_TOP + (v_max - v) / (v_max - v_min) * plot_height;
+
+    for i in 0..=TICKS {
+        let v = v_min + (v_max - v_min) * i as f64 / TICKS as f64;
+        writeln!(
+            writer,
+            "<line x1=\"{x1:.1}\" y1=\"{y:.1}\" x2=\"{x2:.1}\" y2=\"{y:.1}\" stroke=\"#ddd\"/>",
+            x1 = MARGIN_LEFT,
+            x2 = MARGIN_LEFT + plot_width,
+            y = y(v)
+        )?;
+        writeln!(
+            writer,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{:.2}</text>",
+            MARGIN_LEFT - 6.0,
+            y(v) + 4.0,
+            v
+        )?;
+        let t = t_min + ((t_max - t_min) as f64 * i as f64 / TICKS as f64) as i64;
+        writeln!(
+            writer,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x(t),
+            HEIGHT - MARGIN_BOTTOM + 18.0,
+            tz.timestamp(t, 0).format("%Y-%m-%d")
+        )?;
+    }
+    writeln!(
+        writer,
+        "<line x1=\"{x1:.1}\" y1=\"{y:.1}\" x2=\"{x2:.1}\" y2=\"{y:.1}\" stroke=\"#444\"/>",
+        x1 = MARGIN_LEFT,
+        x2 = MARGIN_LEFT + plot_width,
+        y = y(0.0)
+    )?;
+
+    for (i, s) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let mut path = String::new();
+        for (j, (dt, v)) in s.points.iter().enumerate() {
+            let (px, py) = (x(dt.timestamp()), y(*v));
+            if j == 0 {
+                path += &format!("M{:.1},{:.1}", px, py);
+            } else {
+                path += &format!(" H{:.1} V{:.1}", px, py);
+            }
+        }
+        if !path.is_empty() {
+            writeln!(
+                writer,
+                "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+                path, color
+            )?;
+        }
+        let legend_y = MARGIN_TOP + 18.0 * i as f64;
+        writeln!(
+            writer,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"12\" height=\"12\" fill=\"{}\"/>",
+            WIDTH - MARGIN_RIGHT + 16.0,
+            legend_y,
+            color
+        )?;
+        writeln!(
+            writer,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            WIDTH - MARGIN_RIGHT + 34.0,
+            legend_y + 10.0,
+            escape(&s.name)
+        )?;
+    }
+
+    writeln!(writer, "</svg>")?;
+    writer.flush()
+}
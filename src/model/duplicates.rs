@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::{
+    model::{
+        event::{event_location, Event},
+        trade::{Source, Trade},
+    },
+    utils::time_utils::datetime_to_str,
+};
+
+/// Relative difference of volumes below which trades are considered the same.
+const VOLUME_TOLERANCE: f64 = 1e-6;
+
+/// What to do with trades that duplicate an earlier one, e.g. from overlapping exports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep duplicates as separate trades.
+    KeepAll,
+    /// Refuse to process the events.
+    Reject,
+    /// Drop duplicates.
+    KeepFirst,
+    /// Drop duplicates, appending their notes to the first trade.
+    MergeNotes,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep-all" => Ok(Self::KeepAll),
+            "reject" => Ok(Self::Reject),
+            "keep-first" => Ok(Self::KeepFirst),
+            "merge-notes" => Ok(Self::MergeNotes),
+            _ => Err(format!("Unknown duplicate policy: '{}'", s)),
+        }
+    }
+}
+
+/// Trade found to duplicate an earlier one.
+pub struct Duplicate {
+    /// Position of the duplicate in the events, starting from 1.
+    pub event_no: usize,
+    /// Position of the first trade it duplicates, starting from 1.
+    pub original_no: usize,
+    pub datetime: DateTime<Tz>,
+    pub source: Option<Source>,
+    pub original_source: Option<Source>,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}]: duplicates {}",
+            event_location(self.event_no, self.source.as_ref()),
+            datetime_to_str(&self.datetime),
+            event_location(self.original_no, self.original_source.as_ref())
+        )
+    }
+}
+
+fn is_same_volume(v_1: f64, v_2: f64) -> bool {
+    (v_1 - v_2).abs() <= VOLUME_TOLERANCE * v_1.abs().max(v_2.abs())
+}
+
+/// Whether the trades happened at the same time on the same exchange with the same currencies
+/// and volumes within the tolerance.
+pub fn is_duplicate(trade: &Trade, other: &Trade) -> bool {
+    trade.datetime == other.datetime
+        && trade.exchange_name == other.exchange_name
+        && trade.currency_from == other.currency_from
+        && trade.currency_to == other.currency_to
+        && is_same_volume(trade.volume_from, other.volume_from)
+        && is_same_volume(trade.volume_to, other.volume_to)
+}
+
+/// Trades duplicating an earlier trade of the events, in order.
+pub fn find_duplicates(events: &[Event]) -> Vec<Duplicate> {
+    // Indices of the first trades seen per time, exchange and currencies.
+    let mut originals = HashMap::<(i64, &str, &str, &str), Vec<usize>>::new();
+    let mut duplicates = vec![];
+    for (i, event) in events.iter().enumerate() {
+        let trade = match event {
+            Event::Trade(trade) => trade,
+            _ => continue,
+        };
+        let key = (
+            trade.datetime.timestamp(),
+            trade.exchange_name.as_str(),
+            trade.currency_from.as_str(),
+            trade.currency_to.as_str(),
+        );
+        let candidates = originals.entry(key).or_default();
+        let original = candidates.iter().copied().find(|&j| match &events[j] {
+            Event::Trade(other) => is_duplicate(trade, other),
+            _ => false,
+        });
+        match original {
+            Some(j) => duplicates.push(Duplicate {
+                event_no: i + 1,
+                original_no: j + 1,
+                datetime: trade.datetime,
+                source: trade.source.clone(),
+                original_source: events[j].source().cloned(),
+            }),
+            None => candidates.push(i),
+        }
+    }
+    duplicates
+}
+
+impl DuplicatePolicy {
+    /// Applies the policy to the duplicates found in the events, returning the events to process,
+    /// or None if the policy rejects them.
+    pub fn apply(&self, mut events: Vec<Event>, duplicates: &[Duplicate]) -> Option<Vec<Event>> {
+        match self {
+            Self::KeepAll => return Some(events),
+            Self::Reject if !duplicates.is_empty() => return None,
+            Self::MergeNotes => {
+                for duplicate in duplicates {
+                    let notes = events[duplicate.event_no - 1].notes().to_vec();
+                    let original_notes = events[duplicate.original_no - 1].notes_mut();
+                    for note in notes {
+                        if !original_notes.contains(&note) {
+                            original_notes.push(note);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        let mut event_no = 0;
+        let mut duplicates = duplicates.iter().peekable();
+        events.retain(|_| {
+            event_no += 1;
+            let is_duplicate = duplicates.peek().is_some_and(|d| d.event_no == event_no);
+            if is_duplicate {
+                duplicates.next();
+            }
+            !is_duplicate
+        });
+        Some(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+
+    fn trade(hour: u32, volume_to: f64, note: &str) -> Event {
+        Event::Trade(Trade {
+            datetime: UTC.ymd(2020, 1, 1).and_hms(hour, 0, 0),
+            exchange_name: "Exchange".to_owned(),
+            volume_from: 7000.0,
+            currency_from: "USD".to_owned(),
+            currency_from_price_usd: 1.0,
+            volume_to,
+            currency_to: "BTC".to_owned(),
+            currency_to_price_usd: 7000.0,
+            notes: vec![note.to_owned()],
+            source: None,
+        })
+    }
+
+    /// A trade, another one at the same time, its duplicate within the tolerance and a later one.
+    fn events() -> Vec<Event> {
+        vec![
+            trade(1, 1.0, "first"),
+            trade(1, 0.5, "other"),
+            trade(1, 1.0 + 1e-8, "second"),
+            trade(2, 1.0, "later"),
+        ]
+    }
+
+    fn notes(events: &[Event]) -> Vec<Vec<String>> {
+        events.iter().map(|event| event.notes().to_vec()).collect()
+    }
+
+    #[test]
+    fn duplicates_are_found_within_the_tolerance() {
+        let duplicates = find_duplicates(&events());
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].event_no, 3);
+        assert_eq!(duplicates[0].original_no, 1);
+    }
+
+    #[test]
+    fn policies_are_applied() {
+        let events = events();
+        let duplicates = find_duplicates(&events);
+
+        let kept = DuplicatePolicy::KeepAll.apply(events.clone(), &duplicates);
+        assert_eq!(kept.unwrap().len(), 4);
+        assert!(DuplicatePolicy::Reject
+            .apply(events.clone(), &duplicates)
+            .is_none());
+        assert_eq!(
+            DuplicatePolicy::Reject
+                .apply(events.clone(), &[])
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let kept = DuplicatePolicy::KeepFirst
+            .apply(events.clone(), &duplicates)
+            .unwrap();
+        assert_eq!(
+            notes(&kept),
+            vec![vec!["first"], vec!["other"], vec!["later"]]
+        );
+
+        let kept = DuplicatePolicy::MergeNotes
+            .apply(events, &duplicates)
+            .unwrap();
+        assert_eq!(
+            notes(&kept),
+            vec![vec!["first", "second"], vec!["other"], vec!["later"]]
+        );
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    model::{
+        apply_aliases,
+        json::DERIVED_RECORD_TYPES,
+        trade::{
+            check_price_currency, deserialize_price, missing_price, parse_number, parse_price,
+            Source, Trade,
+        },
+        Currency,
+    },
+    utils::{
+        json_utils::read_jsonl_with_lines,
+        read_all_lines,
+        time_utils::{datetime_from_str, datetime_to_precise_str, serde_datetime},
+    },
+};
+
+/// Token migration: every held unit of `currency_from` becomes `ratio` units of `currency_to`,
+/// keeping cost basis and acquisition dates of the lots.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Migration {
+    #[serde(with = "serde_datetime")]
+    pub datetime: DateTime<Tz>,
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub ratio: f64,
+    #[serde(default)]
+    pub notes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+impl Migration {
+    /// Parses a migration line, None if the line is not one.
+    pub fn parse(line: &str, tz: &Tz) -> Result<Option<Self>, String> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(concat!(
+                r"^\[(?P<datetime>.+)\] MIGRATION ",
+                r"(?P<currency_from>\w+) => (?P<currency_to>\w+) \(1:(?P<ratio>\S+)\)$"
+            ))
+            .unwrap();
+        }
+
+        let caps = match RE.captures(line) {
+            Some(caps) => caps,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            datetime: datetime_from_str(&caps["datetime"], tz).map_err(|err| err.to_string())?,
+            currency_from: caps["currency_from"].to_string(),
+            currency_to: caps["currency_to"].to_string(),
+            ratio: parse_number(&caps["ratio"])?,
+            notes: vec![],
+            source: None,
+        }))
+    }
+}
+
+/// Chain split: holders of `currency_from` receive `ratio` units of `currency_to` per unit held.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fork {
+    #[serde(with = "serde_datetime")]
+    pub datetime: DateTime<Tz>,
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub ratio: f64,
+    #[serde(default = "missing_price", deserialize_with = "deserialize_price")]
+    pub currency_to_price_usd: f64,
+    #[serde(default)]
+    pub notes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+impl Fork {
+    /// Parses a fork line, None if the line is not one.
+    pub fn parse(line: &str, tz: &Tz) -> Result<Option<Self>, String> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(concat!(
+                r"^\[(?P<datetime>.+)\] FORK ",
+                r"(?P<currency_from>\w+) => (?P<currency_to>\w+) \(1:(?P<ratio>\S+), ",
+                r"(?P<currency_to_2>\w+)=(?P<currency_to_price_usd>\S+)\)$"
+            ))
+            .unwrap();
+        }
+
+        let caps = match RE.captures(line) {
+            Some(caps) => caps,
+            None => return Ok(None),
+        };
+        let currency_to = caps["currency_to"].to_string();
+        check_price_currency(&currency_to, &caps["currency_to_2"])?;
+        Ok(Some(Self {
+            datetime: datetime_from_str(&caps["datetime"], tz).map_err(|err| err.to_string())?,
+            currency_from: caps["currency_from"].to_string(),
+            currency_to,
+            ratio: parse_number(&caps["ratio"])?,
+            currency_to_price_usd: parse_price(&caps["currency_to_price_usd"])?,
+            notes: vec![],
+            source: None,
+        }))
+    }
+}
+
+/// Entry of the input file.
+/// In JSON it is tagged with its `type`, which defaults to `trade` when absent.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Trade(Trade),
+    Migration(Migration),
+    Fork(Fork),
+}
+
+impl Event {
+    /// Parses a line of a text input file, see `Trade::parse`.
+    pub fn parse(line: &str, tz: &Tz) -> Result<Self, String> {
+        if let Some(migration) = Migration::parse(line, tz)? {
+            Ok(Event::Migration(migration))
+        } else if let Some(fork) = Fork::parse(line, tz)? {
+            Ok(Event::Fork(fork))
+        } else {
+            Trade::parse(line, tz).map(Event::Trade)
+        }
+    }
+
+    pub fn datetime(&self) -> &DateTime<Tz> {
+        match self {
+            Event::Trade(t) => &t.datetime,
+            Event::Migration(m) => &m.datetime,
+            Event::Fork(f) => &f.datetime,
+        }
+    }
+
+    pub(crate) fn datetime_mut(&mut self) -> &mut DateTime<Tz> {
+        match self {
+            Event::Trade(t) => &mut t.datetime,
+            Event::Migration(m) => &mut m.datetime,
+            Event::Fork(f) => &mut f.datetime,
+        }
+    }
+
+    /// Identity of the event regardless of its notes and prices, used to skip it when imported
+    /// again.
+    pub fn fingerprint(&self) -> String {
+        match self {
+            Event::Trade(t) => t.fingerprint(),
+            Event::Migration(m) => format!(
+                "migration|{}|{}|{}|{}",
+                m.datetime.timestamp_millis(),
+                m.currency_from,
+                m.currency_to,
+                m.ratio
+            ),
+            Event::Fork(f) => format!(
+                "fork|{}|{}|{}|{}",
+                f.datetime.timestamp_millis(),
+                f.currency_from,
+                f.currency_to,
+                f.ratio
+            ),
+        }
+    }
+
+    pub fn notes(&self) -> &[String] {
+        match self {
+            Event::Trade(t) => &t.notes,
+            Event::Migration(m) => &m.notes,
+            Event::Fork(f) => &f.notes,
+        }
+    }
+
+    /// Where the event was read from, if known.
+    pub fn source(&self) -> Option<&Source> {
+        match self {
+            Event::Trade(t) => t.source.as_ref(),
+            Event::Migration(m) => m.source.as_ref(),
+            Event::Fork(f) => f.source.as_ref(),
+        }
+    }
+
+    fn source_mut(&mut self) -> &mut Option<Source> {
+        match self {
+            Event::Trade(t) => &mut t.source,
+            Event::Migration(m) => &mut m.source,
+            Event::Fork(f) => &mut f.source,
+        }
+    }
+
+    pub(crate) fn notes_mut(&mut self) -> &mut Vec<String> {
+        match self {
+            Event::Trade(t) => &mut t.notes,
+            Event::Migration(m) => &mut m.notes,
+            Event::Fork(f) => &mut f.notes,
+        }
+    }
+
+    /// Renames currencies as per the alias table (e.g. XBT => BTC).
+    pub fn apply_aliases(&mut self, aliases: &HashMap<Currency, Currency>) {
+        match self {
+            Event::Trade(t) => apply_aliases(aliases, &mut t.currency_from, &mut t.currency_to),
+            Event::Migration(m) => apply_aliases(aliases, &mut m.currency_from, &mut m.currency_to),
+            Event::Fork(f) => apply_aliases(aliases, &mut f.currency_from, &mut f.currency_to),
+        }
+    }
+}
+
+/// Error loading events from an input file, with the line it is on if known.
+#[derive(Debug)]
+pub struct LoadError {
+    pub file: String,
+    /// Line number starting from 1.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Extensions of the files loaded from input directories.
+const INPUT_EXTENSIONS: &[&str] = &["txt", "json", "jsonl"];
+
+/// Loads events from a text file, or from a JSON (`.json`) / JSON Lines (`.jsonl`) file, with
+/// datetimes without offset in `tz`. Events without a source get the file and line they were
+/// read from, and for JSON the `id` of the record as its row id.
+///
+/// A JSON file is either an array of events or a wallet written by `WalletJson`, of which only
+/// the trades are loaded. Records of a JSON Lines file computed from trades, such as `sell_trade`
+/// or `holding`, are skipped.
+pub fn load_events(filename: &str, tz: &Tz) -> Result<Vec<Event>, LoadError> {
+    let load_error = |line: Option<usize>, message: String| LoadError {
+        file: filename.to_owned(),
+        line,
+        message,
+    };
+    let file = File::open(filename).map_err(|err| load_error(None, err.to_string()))?;
+    let reader = BufReader::new(file);
+    let (importer, records): (&str, Vec<(Option<usize>, Value)>) =
+        match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => {
+                let value: Value = serde_json::from_reader(reader)
+                    .map_err(|err| load_error(Some(err.line()), err.to_string()))?;
+                let values = match value {
+                    Value::Array(values) => values,
+                    Value::Object(mut fields) => match fields.remove("trades") {
+                        Some(Value::Array(trades)) => trades,
+                        _ => {
+                            return Err(load_error(
+                                None,
+                                "expected an array of events or a wallet with trades".to_owned(),
+                            ))
+                        }
+                    },
+                    _ => return Err(load_error(None, "expected an array of events".to_owned())),
+                };
+                (
+                    "json",
+                    values.into_iter().map(|value| (None, value)).collect(),
+                )
+            }
+            Some("jsonl") => (
+                "jsonl",
+                read_jsonl_with_lines(reader)
+                    .map_err(|(line, err)| load_error(Some(line), err.to_string()))?
+                    .into_iter()
+                    .filter(|(_, value): &(usize, Value)| {
+                        !matches!(value.get("type"), Some(Value::String(t)) if DERIVED_RECORD_TYPES.contains(&t.as_str()))
+                    })
+                    .map(|(line, value)| (Some(line), value))
+                    .collect(),
+            ),
+            _ => {
+                let lines =
+                    read_all_lines(reader).map_err(|err| load_error(None, err.to_string()))?;
+                let events = parse_events(&lines, tz)
+                    .map_err(|(line, message)| load_error(Some(line), message))?;
+                return Ok(events
+                    .into_iter()
+                    .map(|(line, mut event)| {
+                        set_source(&mut event, filename, Some(line), "text", None);
+                        event
+                    })
+                    .collect());
+            }
+        };
+    let mut events = vec![];
+    for (i, (line, value)) in records.into_iter().enumerate() {
+        let row_id = match value.get("id") {
+            Some(Value::String(id)) => Some(id.clone()),
+            Some(Value::Number(id)) => Some(id.to_string()),
+            _ => None,
+        };
+        let mut event = event_from_json(value, tz).map_err(|message| match line {
+            Some(_) => load_error(line, message),
+            None => load_error(None, format!("record #{}: {}", i + 1, message)),
+        })?;
+        set_source(&mut event, filename, line, importer, row_id);
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Sets the source of the event unless the record already had one.
+fn set_source(
+    event: &mut Event,
+    filename: &str,
+    line: Option<usize>,
+    importer: &str,
+    row_id: Option<String>,
+) {
+    event.source_mut().get_or_insert_with(|| Source {
+        file: filename.to_owned(),
+        line,
+        importer: importer.to_owned(),
+        row_id,
+    });
+}
+
+/// Source of an event for messages, or its position in the input if unknown (e.g. `event #3`).
+pub fn event_location(event_no: usize, source: Option<&Source>) -> String {
+    match source {
+        Some(source) => source.to_string(),
+        None => format!("event #{}", event_no),
+    }
+}
+
+/// Loads events from files, directories and glob patterns, see `load_events`. Events of several
+/// files are merged in chronological order, events at the same time keeping the order of the
+/// files and within them.
+pub fn load_events_from(paths: &[String], tz: &Tz) -> Result<Vec<Event>, LoadError> {
+    let filenames = expand_input_paths(paths)?;
+    match filenames.as_slice() {
+        [] => {
+            return Err(LoadError {
+                file: paths.join(", "),
+                line: None,
+                message: "No input files".to_owned(),
+            })
+        }
+        [filename] => return load_events(filename, tz),
+        _ => {}
+    }
+    let mut events = vec![];
+    for filename in &filenames {
+        events.extend(load_events(filename, tz)?);
+    }
+    // Stable, so ties keep their order.
+    events.sort_by_key(|event| *event.datetime());
+    Ok(events)
+}
+
+/// Replaces directories with their input files and glob patterns with the files matching them,
+/// both sorted by name.
+fn expand_input_paths(paths: &[String]) -> Result<Vec<String>, LoadError> {
+    let mut filenames = vec![];
+    for path in paths {
+        let load_error = |message: String| LoadError {
+            file: path.clone(),
+            line: None,
+            message,
+        };
+        if Path::new(path).is_dir() {
+            let mut entries = vec![];
+            for entry in fs::read_dir(path).map_err(|err| load_error(err.to_string()))? {
+                let p = entry.map_err(|err| load_error(err.to_string()))?.path();
+                if p.is_file()
+                    && p.extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| INPUT_EXTENSIONS.contains(&ext))
+                {
+                    entries.push(p);
+                }
+            }
+            entries.sort();
+            filenames.extend(entries.iter().map(|p| p.to_string_lossy().into_owned()));
+        } else if path.contains(&['*', '?', '['][..]) {
+            let mut matches = vec![];
+            for p in glob::glob(path).map_err(|err| load_error(err.to_string()))? {
+                let p = p.map_err(|err| load_error(err.to_string()))?;
+                if p.is_file() {
+                    matches.push(p);
+                }
+            }
+            matches.sort();
+            filenames.extend(matches.iter().map(|p| p.to_string_lossy().into_owned()));
+        } else {
+            filenames.push(path.clone());
+        }
+    }
+    Ok(filenames)
+}
+
+/// Parses an event of a JSON input file, with a datetime without offset in `tz`.
+fn event_from_json(mut value: Value, tz: &Tz) -> Result<Event, String> {
+    let mut datetime = None;
+    if let Value::Object(fields) = &mut value {
+        fields
+            .entry("type")
+            .or_insert_with(|| Value::String("trade".to_owned()));
+        // Parsed here, as deserializing has no timezone to interpret local times in.
+        if let Some(Value::String(s)) = fields.get_mut("datetime") {
+            let dt = datetime_from_str(s, tz).map_err(|err| err.to_string())?;
+            *s = datetime_to_precise_str(&dt);
+            datetime = Some(dt);
+        }
+    }
+    let mut event: Event = serde_json::from_value(value).map_err(|err| err.to_string())?;
+    if let Some(dt) = datetime {
+        *event.datetime_mut() = dt;
+    }
+    Ok(event)
+}
+
+/// Parses events with the number of the line they start on, starting from 1, or fails with the
+/// line of the first invalid one.
+fn parse_events(lines: &[String], tz: &Tz) -> Result<Vec<(usize, Event)>, (usize, String)> {
+    let mut events = vec![];
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = i + 1;
+        let mut e = Event::parse(&lines[i], tz).map_err(|message| (line, message))?;
+        i += 1;
+        while i < lines.len() && lines[i].starts_with("--- ") {
+            let note = &lines[i][4..];
+            e.notes_mut().push(note.to_string());
+            i += 1;
+        }
+        events.push((line, e));
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::{Tz, US::Pacific, UTC};
+
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|line| line.to_owned()).collect()
+    }
+
+    #[test]
+    fn text_events_with_notes_and_aliases() {
+        let lines = lines(concat!(
+            "[2020-01-01 00:00:00+00:00] 7000 USD => 1 XBT (USD=1, XBT=7000, Exchange)\n",
+            "--- first note\n",
+            "--- second note\n",
+            "[2020-02-01 00:00:00+00:00] MIGRATION XBT => BTCX (1:10)\n",
+            "[2020-03-01 00:00:00+00:00] FORK XBT => BCH (1:1, BCH=300)",
+        ));
+        let aliases: HashMap<Currency, Currency> = vec![("XBT".to_owned(), "BTC".to_owned())]
+            .into_iter()
+            .collect();
+        let mut events = parse_events(&lines, &UTC).unwrap();
+        for (_, event) in &mut events {
+            event.apply_aliases(&aliases);
+        }
+
+        let line_numbers: Vec<usize> = events.iter().map(|(line, _)| *line).collect();
+        assert_eq!(line_numbers, vec![1, 4, 5]);
+        match &events[0].1 {
+            Event::Trade(trade) => {
+                assert_eq!(trade.currency_to, "BTC");
+                assert_eq!(trade.notes, vec!["first note", "second note"]);
+            }
+            _ => panic!("expected a trade"),
+        }
+        match &events[1].1 {
+            Event::Migration(migration) => {
+                assert_eq!(migration.currency_from, "BTC");
+                assert_eq!(migration.currency_to, "BTCX");
+                assert_eq!(migration.ratio, 10.0);
+            }
+            _ => panic!("expected a migration"),
+        }
+        match &events[2].1 {
+            Event::Fork(fork) => {
+                assert_eq!(fork.currency_from, "BTC");
+                assert_eq!(fork.currency_to_price_usd, 300.0);
+            }
+            _ => panic!("expected a fork"),
+        }
+    }
+
+    #[test]
+    fn invalid_text_event_is_reported_with_its_line() {
+        let lines = lines(concat!(
+            "[2020-01-01 00:00:00+00:00] 7000 USD => 1 BTC (USD=1, BTC=7000, Exchange)\n",
+            "--- note\n",
+            "[2020-01-02 00:00:00+00:00] 7000 USD => 1 BTC",
+        ));
+        match parse_events(&lines, &UTC) {
+            Err((line, _)) => assert_eq!(line, 3),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn json_event_in_the_timezone() {
+        let tz: Tz = Pacific;
+        let value = serde_json::json!({
+            "datetime": "2020-01-01 12:00:00",
+            "exchange_name": "Exchange",
+            "volume_from": 7000.0,
+            "currency_from": "USD",
+            "currency_from_price_usd": 1.0,
+            "volume_to": 1.0,
+            "currency_to": "BTC",
+            "currency_to_price_usd": 7000.0,
+        });
+        let event = event_from_json(value, &tz).unwrap();
+        assert_eq!(*event.datetime(), tz.ymd(2020, 1, 1).and_hms(12, 0, 0));
+        assert!(matches!(event, Event::Trade(_)));
+    }
+}
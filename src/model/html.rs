@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::{
+    model::{period::PeriodScheme, summary::PeriodGains, trade::Trade, wallet::Wallet},
+    utils::time_utils::{datetime_to_str, is_datetime_within_limits},
+};
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{border:1px solid #ccc;padding:.25em .5em;text-align:right}\
+th{background:#eee}td.text{text-align:left}\
+tr:target{background:#ffd}.notes{color:#666;font-size:.9em}\
+.negative{color:#b00}";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn amount(x: f64) -> String {
+    if x < 0.0 {
+        format!("<td class=\"negative\">{:.9}</td>", x)
+    } else {
+        format!("<td>{:.9}</td>", x)
+    }
+}
+
+fn text(s: &str) -> String {
+    format!("<td class=\"text\">{}</td>", escape(s))
+}
+
+fn trade_link(trade_idx: usize, trade: &Trade) -> String {
+    format!(
+        "<td class=\"text\"><a href=\"#trade-{}\">{}</a></td>",
+        trade_idx,
+        datetime_to_str(&trade.datetime)
+    )
+}
+
+/// Writes a self-contained HTML report of the period: realized gains, quarterly breakdown, sell
+/// trades linked to the trades they come from, holdings at the end of the period, fees and the
+/// trade log, which also lists earlier trades that sold lots or held lots come from.
+pub fn write_html_report<W: Write>(
+    mut writer: W,
+    wallet: &Wallet,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) -> io::Result<()> {
+    let title = format!(
+        "Trades report {} - {}",
+        datetime_to_str(dt_from),
+        datetime_to_str(dt_to)
+    );
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", escape(&title))?;
+    writeln!(writer, "<style>{}</style>", STYLE)?;
+    writeln!(writer, "</head><body>")?;
+    writeln!(writer, "<h1>{}</h1>", escape(&title))?;
+    writeln!(
+        writer,
+        "<p>Amounts in {}.</p>",
+        escape(wallet.base_currency())
+    )?;
+
+    writeln!(writer, "<h2>Realized gains</h2>")?;
+    write_period_gains(
+        &mut writer,
+        &wallet.gains_for_period("Target period", dt_from, dt_to),
+    )?;
+
+    writeln!(writer, "<h2>Quarterly breakdown</h2>")?;
+    for period in wallet
+        .gains_per_period(dt_from, dt_to, PeriodScheme::Quarterly)
+        .iter()
+        .skip(1)
+    {
+        write_period_gains(&mut writer, period)?;
+    }
+
+    write_sell_trades(&mut writer, wallet, dt_from, dt_to)?;
+    write_holdings(&mut writer, wallet, dt_to)?;
+    write_fees(&mut writer, wallet, dt_from, dt_to)?;
+    write_trades(&mut writer, wallet, dt_from, dt_to)?;
+
+    writeln!(writer, "</body></html>")?;
+    writer.flush()
+}
+
+fn write_period_gains<W: Write>(writer: &mut W, period: &PeriodGains) -> io::Result<()> {
+    writeln!(writer, "<h3>{}</h3>", escape(&period.name))?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Term</th><th>Currency</th><th>Volume</th><th>Proceeds</th><th>Cost basis</th><th>Gain</th></tr>"
+    )?;
+    for (gains, term) in &[(&period.long_term, "Long"), (&period.short_term, "Short")] {
+        for info in &gains.currencies {
+            writeln!(
+                writer,
+                "<tr>{}{}{}{}{}{}</tr>",
+                text(term),
+                text(info.currency.as_deref().unwrap_or_default()),
+                amount(info.volume),
+                amount(info.proceeds),
+                amount(info.cost_basis),
+                amount(info.gain)
+            )?;
+        }
+        let total = &gains.total;
+        writeln!(
+            writer,
+            "<tr><th class=\"text\">{}</th><th class=\"text\">Total</th>{}{}{}{}</tr>",
+            term,
+            amount(total.volume),
+            amount(total.proceeds),
+            amount(total.cost_basis),
+            amount(total.gain)
+        )?;
+    }
+    writeln!(writer, "</table>")
+}
+
+fn write_sell_trades<W: Write>(
+    writer: &mut W,
+    wallet: &Wallet,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) -> io::Result<()> {
+    writeln!(writer, "<h2>Sell trades</h2>")?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Currency</th><th>Volume</th><th>Bought</th><th>Sold</th><th>Proceeds</th><th>Cost basis</th><th>Gain</th><th>Term</th></tr>"
+    )?;
+    for details in wallet.sell_trade_details(dt_from, dt_to) {
+        let st = details.sell_trade;
+        let bought = match (st.buy_trade_idx, details.buy) {
+            (Some(idx), Some(buy)) => trade_link(idx, buy),
+            _ => text(&format!("fork {}", datetime_to_str(&st.buy_datetime))),
+        };
+        writeln!(
+            writer,
+            "<tr>{}{}{}{}{}{}{}{}</tr>",
+            text(&st.currency),
+            amount(st.volume),
+            bought,
+            trade_link(st.sell_trade_idx, details.sell),
+            amount(st.proceeds),
+            amount(st.cost_basis),
+            amount(st.gain()),
+            text(if st.is_long_term() { "long" } else { "short" })
+        )?;
+    }
+    writeln!(writer, "</table>")
+}
+
+fn write_holdings<W: Write>(
+    writer: &mut W,
+    wallet: &Wallet,
+    dt_to: &DateTime<Tz>,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "<h2>Holdings at {}</h2>",
+        escape(&datetime_to_str(dt_to))
+    )?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Currency</th><th>Volume</th><th>Cost basis</th><th>Price (avg cost)</th><th>Acquired</th></tr>"
+    )?;
+    let wallet_at = wallet.at(dt_to);
+    for holdings in wallet_at.holdings_summary() {
+        writeln!(
+            writer,
+            "<tr><th class=\"text\">{}</th>{}{}{}<td></td></tr>",
+            escape(&holdings.currency),
+            amount(holdings.volume),
+            amount(holdings.cost_basis),
+            amount(holdings.avg_cost)
+        )?;
+        for lot in &holdings.lots {
+            let acquired = match lot.trade_idx {
+                Some(idx) => trade_link(idx, &wallet.trades[idx]),
+                None => text(&format!("fork {}", datetime_to_str(&lot.datetime))),
+            };
+            writeln!(
+                writer,
+                "<tr><td></td>{}{}{}{}</tr>",
+                amount(lot.volume),
+                amount(lot.cost_basis),
+                amount(lot.price_usd),
+                acquired
+            )?;
+        }
+    }
+    writeln!(writer, "</table>")
+}
+
+fn write_fees<W: Write>(
+    writer: &mut W,
+    wallet: &Wallet,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) -> io::Result<()> {
+    let mut fees_per_exchange = BTreeMap::<&str, f64>::new();
+    for trade in wallet.trades_within(dt_from, dt_to) {
+        *fees_per_exchange.entry(&trade.exchange_name).or_default() += trade.fees_usd();
+    }
+    writeln!(writer, "<h2>Fees</h2>")?;
+    writeln!(writer, "<table>")?;
+    writeln!(writer, "<tr><th>Exchange</th><th>Fees</th></tr>")?;
+    let mut total = 0.0;
+    for (exchange, fees) in &fees_per_exchange {
+        total += fees;
+        writeln!(writer, "<tr>{}{}</tr>", text(exchange), amount(*fees))?;
+    }
+    writeln!(
+        writer,
+        "<tr><th class=\"text\">Total</th>{}</tr>",
+        amount(total)
+    )?;
+    writeln!(writer, "</table>")
+}
+
+fn write_trades<W: Write>(
+    writer: &mut W,
+    wallet: &Wallet,
+    dt_from: &DateTime<Tz>,
+    dt_to: &DateTime<Tz>,
+) -> io::Result<()> {
+    writeln!(writer, "<h2>Trades</h2>")?;
+    writeln!(writer, "<table>")?;
+    writeln!(
+        writer,
+        "<tr><th>Time</th><th>Exchange</th><th>Sold</th><th></th><th>Price</th><th>Bought</th><th></th><th>Price</th><th>Fees</th><th>Source</th></tr>"
+    )?;
+    let mut referenced: BTreeSet<usize> = wallet
+        .sell_trade_details(dt_from, dt_to)
+        .iter()
+        .filter_map(|details| details.sell_trade.buy_trade_idx)
+        .collect();
+    for lots in wallet.at(dt_to).holdings().values() {
+        referenced.extend(lots.iter().filter_map(|lot| lot.trade_idx));
+    }
+    for (i, trade) in wallet.trades.iter().enumerate() {
+        if !is_datetime_within_limits(&trade.datetime, dt_from, dt_to) && !referenced.contains(&i) {
+            continue;
+        }
+        writeln!(
+            writer,
+            "<tr id=\"trade-{}\">{}{}{}{}{}{}{}{}{}{}</tr>",
+            i,
+            text(&datetime_to_str(&trade.datetime)),
+            text(&trade.exchange_name),
+            amount(trade.volume_from),
+            text(&trade.currency_from),
+            amount(trade.currency_from_price_usd),
+            amount(trade.volume_to),
+            text(&trade.currency_to),
+            amount(trade.currency_to_price_usd),
+            amount(trade.fees_usd()),
+            text(
+                &trade
+                    .source
+                    .as_ref()
+                    .map(|source| source.to_string())
+                    .unwrap_or_default()
+            )
+        )?;
+        for note in &trade.notes {
+            writeln!(
+                writer,
+                "<tr class=\"notes\"><td></td><td class=\"text\" colspan=\"9\">{}</td></tr>",
+                escape(note)
+            )?;
+        }
+    }
+    writeln!(writer, "</table>")
+}
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{
+        sell_trade::SellTrade,
+        trade::Trade,
+        usd_trade::UsdTrade,
+        wallet::{HoldingsItem, Wallet},
+        Currency,
+    },
+    utils::json_utils::{read_jsonl, write_json, write_jsonl},
+};
+
+/// Holdings lot together with its currency.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Holding {
+    pub currency: Currency,
+    #[serde(flatten)]
+    pub item: HoldingsItem,
+}
+
+/// JSON representation of the wallet state. Input files in this format are loaded as its trades,
+/// see `load_events`.
+/// `buy_trade_idx`, `sell_trade_idx` and `trade_idx` are indices into `trades`, null for lots
+/// received in forks.
+#[derive(Serialize, Deserialize)]
+pub struct WalletJson {
+    pub trades: Vec<Trade>,
+    pub sell_trades: Vec<SellTrade>,
+    pub usd_trades: Vec<UsdTrade>,
+    pub holdings: Vec<Holding>,
+    #[serde(default)]
+    pub fx_sell_trades: Vec<SellTrade>,
+}
+
+/// Types of the JSON Lines records computed from trades, skipped when loading them back as events.
+pub(crate) const DERIVED_RECORD_TYPES: &[&str] =
+    &["sell_trade", "usd_trade", "holding", "fx_sell_trade"];
+
+/// Single line of the JSON Lines representation, tagged with its `type`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonRecord {
+    Trade(Trade),
+    SellTrade(SellTrade),
+    UsdTrade(UsdTrade),
+    Holding(Holding),
+    FxSellTrade(SellTrade),
+}
+
+impl WalletJson {
+    pub fn new(wallet: &Wallet) -> Self {
+        let mut currencies: Vec<&Currency> = wallet.holdings().keys().collect();
+        currencies.sort();
+        let holdings = currencies
+            .into_iter()
+            .flat_map(|currency| {
+                wallet.holdings()[currency].iter().map(move |item| Holding {
+                    currency: currency.clone(),
+                    item: item.clone(),
+                })
+            })
+            .collect();
+
+        Self {
+            trades: wallet.trades.clone(),
+            sell_trades: wallet.sell_trades().to_vec(),
+            usd_trades: wallet.usd_trades().to_vec(),
+            holdings,
+            fx_sell_trades: wallet.fx_sell_trades().to_vec(),
+        }
+    }
+
+    pub fn into_records(self) -> Vec<JsonRecord> {
+        let mut records = vec![];
+        records.extend(self.trades.into_iter().map(JsonRecord::Trade));
+        records.extend(self.sell_trades.into_iter().map(JsonRecord::SellTrade));
+        records.extend(self.usd_trades.into_iter().map(JsonRecord::UsdTrade));
+        records.extend(self.holdings.into_iter().map(JsonRecord::Holding));
+        records.extend(self.fx_sell_trades.into_iter().map(JsonRecord::FxSellTrade));
+        records
+    }
+
+    pub fn from_records(records: Vec<JsonRecord>) -> Self {
+        let mut wallet_json = Self {
+            trades: vec![],
+            sell_trades: vec![],
+            usd_trades: vec![],
+            holdings: vec![],
+            fx_sell_trades: vec![],
+        };
+        for record in records {
+            match record {
+                JsonRecord::Trade(t) => wallet_json.trades.push(t),
+                JsonRecord::SellTrade(t) => wallet_json.sell_trades.push(t),
+                JsonRecord::UsdTrade(t) => wallet_json.usd_trades.push(t),
+                JsonRecord::Holding(h) => wallet_json.holdings.push(h),
+                JsonRecord::FxSellTrade(t) => wallet_json.fx_sell_trades.push(t),
+            }
+        }
+        wallet_json
+    }
+
+    /// Reads a JSON (or JSON Lines if the extension is `.jsonl`) file.
+    pub fn read(filename: &str) -> serde_json::Result<Self> {
+        let file = File::open(filename).map_err(serde_json::Error::io)?;
+        let reader = BufReader::new(file);
+        if is_jsonl(filename) {
+            read_jsonl(reader).map(Self::from_records)
+        } else {
+            serde_json::from_reader(reader)
+        }
+    }
+
+    /// Writes a JSON (or JSON Lines if the extension is `.jsonl`) file.
+    pub fn write(self, filename: &str) -> serde_json::Result<()> {
+        let file = File::create(filename).map_err(serde_json::Error::io)?;
+        let writer = BufWriter::new(file);
+        if is_jsonl(filename) {
+            write_jsonl(writer, &self.into_records())
+        } else {
+            write_json(writer, &self)
+        }
+    }
+}
+
+fn is_jsonl(filename: &str) -> bool {
+    Path::new(filename).extension().and_then(|ext| ext.to_str()) == Some("jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::{event::load_events, wallet::WalletConfig};
+
+    fn trade(
+        day: u32,
+        volume_from: f64,
+        currency_from: &str,
+        volume_to: f64,
+        currency_to: &str,
+    ) -> Trade {
+        Trade {
+            datetime: UTC.ymd(2020, 1, day).and_hms(12, 30, 0),
+            exchange_name: "Exchange".to_owned(),
+            volume_from,
+            currency_from: currency_from.to_owned(),
+            currency_from_price_usd: volume_to / volume_from,
+            volume_to,
+            currency_to: currency_to.to_owned(),
+            currency_to_price_usd: 1.0,
+            notes: vec!["note".to_owned()],
+            source: None,
+        }
+    }
+
+    fn wallet() -> Wallet {
+        let mut wallet = Wallet::new(WalletConfig::default());
+        wallet.add_trade(trade(1, 7000.0, "USD", 1.0, "BTC"));
+        wallet.add_trade(trade(2, 0.5, "BTC", 4000.0, "USD"));
+        wallet
+    }
+
+    fn round_trip(extension: &str) {
+        let filename = std::env::temp_dir()
+            .join(format!(
+                "rusty-trades-round-trip-{}.{}",
+                std::process::id(),
+                extension
+            ))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let wallet = wallet();
+        WalletJson::new(&wallet).write(&filename).unwrap();
+        let events = load_events(&filename, &UTC).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(events.len(), wallet.trades.len());
+        for (event, trade) in events.iter().zip(&wallet.trades) {
+            assert_eq!(event.fingerprint(), trade.fingerprint());
+            assert_eq!(event.notes(), trade.notes.as_slice());
+        }
+        let mut reloaded = Wallet::new(WalletConfig::default());
+        for event in events {
+            reloaded.add_event(event).unwrap();
+        }
+        assert_eq!(reloaded.sell_trades().len(), wallet.sell_trades().len());
+    }
+
+    #[test]
+    fn json_wallet_loads_back_as_trades() {
+        round_trip("json");
+    }
+
+    #[test]
+    fn jsonl_wallet_loads_back_as_trades() {
+        round_trip("jsonl");
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::{
+    model::{
+        sell_trade::SellTrade,
+        trade::Trade,
+        wallet::{ForkRecord, MigrationRecord, Wallet},
+        Currency,
+    },
+    utils::time_utils::datetime_to_str,
+};
+
+const ACCOUNT_CASH: &str = "Assets:Cash";
+const ACCOUNT_CRYPTO: &str = "Assets:Crypto";
+const ACCOUNT_FEES: &str = "Expenses:Fees";
+const ACCOUNT_GAINS_LONG: &str = "Income:CapitalGains:Long";
+const ACCOUNT_GAINS_SHORT: &str = "Income:CapitalGains:Short";
+const ACCOUNT_FORKS: &str = "Income:Forks";
+
+/// Plain-text accounting syntax of the exported journal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LedgerFormat {
+    Beancount,
+    Ledger,
+}
+
+impl FromStr for LedgerFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "beancount" => Ok(Self::Beancount),
+            "ledger" | "ledger-cli" => Ok(Self::Ledger),
+            _ => Err(format!("Unknown ledger format: '{}'", s)),
+        }
+    }
+}
+
+impl LedgerFormat {
+    fn date_format(&self) -> &'static str {
+        match self {
+            Self::Beancount => "%Y-%m-%d",
+            Self::Ledger => "%Y/%m/%d",
+        }
+    }
+}
+
+/// Lot annotation of a posting: cost basis per unit and acquisition date.
+struct Lot {
+    unit_cost: f64,
+    datetime: DateTime<Tz>,
+}
+
+struct Transaction {
+    datetime: DateTime<Tz>,
+    payee: String,
+    narration: String,
+    comments: Vec<String>,
+    postings: Vec<Posting>,
+}
+
+struct Posting {
+    account: String,
+    volume: f64,
+    currency: Currency,
+    lot: Option<Lot>,
+    price: Option<f64>,
+}
+
+impl Posting {
+    fn base(account: &str, volume: f64, base_currency: &str) -> Self {
+        Self {
+            account: account.to_owned(),
+            volume,
+            currency: base_currency.to_owned(),
+            lot: None,
+            price: None,
+        }
+    }
+
+    /// Posting to the cash account of the currency, converted at the price unless base currency.
+    fn cash(currency: &str, volume: f64, price: f64, base_currency: &str) -> Self {
+        Self {
+            account: format!("{}:{}", ACCOUNT_CASH, currency),
+            volume,
+            currency: currency.to_owned(),
+            lot: None,
+            price: if currency == base_currency {
+                None
+            } else {
+                Some(price)
+            },
+        }
+    }
+
+    /// Weight in the base currency as used by ledger tools to balance transactions.
+    fn weight(&self) -> f64 {
+        match (&self.lot, self.price) {
+            (Some(lot), _) => round(self.volume) * round(lot.unit_cost),
+            (None, Some(price)) => round(self.volume) * round(price),
+            (None, None) => round(self.volume),
+        }
+    }
+
+    fn format(&self, format: LedgerFormat, base_currency: &str) -> String {
+        let mut s = format!("  {}  {:.9} {}", self.account, self.volume, self.currency);
+        if let Some(lot) = &self.lot {
+            let date = lot.datetime.format(format.date_format());
+            match format {
+                LedgerFormat::Beancount => {
+                    s += &format!(" {{{:.9} {}, {}}}", lot.unit_cost, base_currency, date)
+                }
+                LedgerFormat::Ledger => {
+                    s += &format!(" {{{:.9} {}}} [{}]", lot.unit_cost, base_currency, date)
+                }
+            }
+        }
+        if let Some(price) = self.price {
+            s += &format!(" @ {:.9} {}", price, base_currency);
+        }
+        s
+    }
+}
+
+/// Cost basis per unit of the lot bought by the trade, fees included.
+/// Lots are reduced at the cost basis per unit of the sell trades, which is the same.
+fn lot_unit_cost(buy_trade: &Trade) -> f64 {
+    let cost_basis = (buy_trade.volume_to * buy_trade.currency_to_price_usd) + buy_trade.fees_usd();
+    cost_basis / buy_trade.volume_to
+}
+
+/// Rounds to the precision postings are written with, so that weights match the output.
+fn round(x: f64) -> f64 {
+    (x * 1e9).round() / 1e9
+}
+
+/// Builds balanced postings for the trade at `trade_idx` with its sell trades.
+/// Sold lots are reduced at the cost basis of the matching sell trades and realized gains are
+/// posted per term. Fees of sells into cash are posted to `Expenses:Fees` and left out of the
+/// gains, so that gains minus fees match the sell trades. Other fees are part of the cost basis
+/// of the bought lot, as in `Wallet`. Foreign cash is converted at the price at trade.
+fn postings_for_trade(
+    wallet: &Wallet,
+    trade_idx: usize,
+    sell_trades: &[&SellTrade],
+) -> Vec<Posting> {
+    let trade = &wallet.trades[trade_idx];
+    let base_currency = wallet.base_currency();
+    let mut postings = vec![];
+    let mut gain_lt = None;
+    let mut gain_st = None;
+
+    for st in sell_trades {
+        postings.push(Posting {
+            account: format!("{}:{}", ACCOUNT_CRYPTO, st.currency),
+            volume: -st.volume,
+            currency: st.currency.clone(),
+            lot: Some(Lot {
+                unit_cost: st.cost_basis / st.volume,
+                datetime: st.buy_datetime,
+            }),
+            price: Some(trade.currency_from_price_usd),
+        });
+        let gain = if st.is_long_term() {
+            &mut gain_lt
+        } else {
+            &mut gain_st
+        };
+        *gain =
+            Some(gain.unwrap_or(0.0) + st.volume * trade.currency_from_price_usd - st.cost_basis);
+    }
+
+    if wallet.is_cash(&trade.currency_from) {
+        postings.push(Posting::cash(
+            &trade.currency_from,
+            -trade.volume_from,
+            trade.currency_from_price_usd,
+            base_currency,
+        ));
+    }
+
+    if wallet.is_cash(&trade.currency_to) {
+        postings.push(Posting::cash(
+            &trade.currency_to,
+            trade.volume_to,
+            trade.currency_to_price_usd,
+            base_currency,
+        ));
+        postings.push(Posting::base(ACCOUNT_FEES, trade.fees_usd(), base_currency));
+    } else {
+        postings.push(Posting {
+            account: format!("{}:{}", ACCOUNT_CRYPTO, trade.currency_to),
+            volume: trade.volume_to,
+            currency: trade.currency_to.clone(),
+            lot: Some(Lot {
+                unit_cost: lot_unit_cost(trade),
+                datetime: trade.datetime,
+            }),
+            price: Some(trade.currency_to_price_usd),
+        });
+    }
+
+    if let Some(gain) = gain_lt {
+        postings.push(Posting::base(ACCOUNT_GAINS_LONG, -gain, base_currency));
+    }
+    if let Some(gain) = gain_st {
+        postings.push(Posting::base(ACCOUNT_GAINS_SHORT, -gain, base_currency));
+    }
+
+    // The last posting absorbs rounding so that the written transaction balances exactly.
+    let (last, others) = postings.split_last_mut().unwrap();
+    if last.lot.is_none() && last.price.is_none() {
+        last.volume = -others.iter().map(|p| p.weight()).sum::<f64>();
+    }
+
+    postings
+}
+
+/// Builds the transaction of the trade at `trade_idx`, see `postings_for_trade`.
+fn transaction_for_trade(
+    wallet: &Wallet,
+    trade_idx: usize,
+    sell_trades: &[&SellTrade],
+) -> Transaction {
+    let trade = &wallet.trades[trade_idx];
+    let postings = postings_for_trade(wallet, trade_idx, sell_trades);
+    let mut comments = vec![];
+    if !postings.iter().any(|p| p.account == ACCOUNT_FEES) {
+        comments.push(format!(
+            "fees: {:.9} {} (included in cost basis)",
+            trade.fees_usd(),
+            wallet.base_currency()
+        ));
+    }
+    if let Some(source) = &trade.source {
+        comments.push(format!("source: {}", source));
+    }
+    for st in sell_trades {
+        if let Some(source) = &st.buy_source {
+            comments.push(format!(
+                "lot source: {:.9} {} bought {} from {}",
+                st.volume,
+                st.currency,
+                datetime_to_str(&st.buy_datetime),
+                source
+            ));
+        }
+    }
+    comments.extend(trade.notes.iter().cloned());
+
+    Transaction {
+        datetime: trade.datetime,
+        payee: trade.exchange_name.clone(),
+        narration: format!(
+            "{:.9} {} => {:.9} {}",
+            trade.volume_from, trade.currency_from, trade.volume_to, trade.currency_to
+        ),
+        comments,
+        postings,
+    }
+}
+
+/// Builds the transaction converting every migrated lot at the same cost basis and date.
+fn transaction_for_migration(record: &MigrationRecord) -> Transaction {
+    let migration = &record.migration;
+    let mut postings = vec![];
+    for lot in &record.lots {
+        let datetime = lot.datetime;
+        let volume_to = lot.volume * migration.ratio;
+        postings.push(Posting {
+            account: format!("{}:{}", ACCOUNT_CRYPTO, migration.currency_from),
+            volume: -lot.volume,
+            currency: migration.currency_from.clone(),
+            lot: Some(Lot {
+                unit_cost: lot.cost_basis / lot.volume,
+                datetime,
+            }),
+            price: None,
+        });
+        postings.push(Posting {
+            account: format!("{}:{}", ACCOUNT_CRYPTO, migration.currency_to),
+            volume: volume_to,
+            currency: migration.currency_to.clone(),
+            lot: Some(Lot {
+                unit_cost: lot.cost_basis / volume_to,
+                datetime,
+            }),
+            price: None,
+        });
+    }
+
+    Transaction {
+        datetime: migration.datetime,
+        payee: "Migration".to_owned(),
+        narration: format!(
+            "{} => {} (1:{})",
+            migration.currency_from, migration.currency_to, migration.ratio
+        ),
+        comments: migration.notes.clone(),
+        postings,
+    }
+}
+
+/// Builds the transaction adding the lot received in a fork, if any. A non-zero cost basis is
+/// posted as income.
+fn transaction_for_fork(wallet: &Wallet, record: &ForkRecord) -> Option<Transaction> {
+    let fork = &record.fork;
+    let lot = record.lot.as_ref()?;
+    let mut postings = vec![Posting {
+        account: format!("{}:{}", ACCOUNT_CRYPTO, fork.currency_to),
+        volume: lot.volume,
+        currency: fork.currency_to.clone(),
+        lot: Some(Lot {
+            unit_cost: lot.cost_basis / lot.volume,
+            datetime: lot.datetime,
+        }),
+        price: None,
+    }];
+    if lot.cost_basis > 0.0 {
+        let weight = postings[0].weight();
+        postings.push(Posting::base(
+            ACCOUNT_FORKS,
+            -weight,
+            wallet.base_currency(),
+        ));
+    }
+
+    Some(Transaction {
+        datetime: fork.datetime,
+        payee: "Fork".to_owned(),
+        narration: format!(
+            "{} => {} (1:{})",
+            fork.currency_from, fork.currency_to, fork.ratio
+        ),
+        comments: fork.notes.clone(),
+        postings,
+    })
+}
+
+fn write_transaction<W: Write>(
+    writer: &mut W,
+    transaction: &Transaction,
+    format: LedgerFormat,
+    base_currency: &str,
+) -> io::Result<()> {
+    let date = transaction.datetime.format(format.date_format());
+    match format {
+        LedgerFormat::Beancount => writeln!(
+            writer,
+            "{} * \"{}\" \"{}\"",
+            date,
+            transaction.payee.replace('"', "'"),
+            transaction.narration
+        )?,
+        LedgerFormat::Ledger => {
+            writeln!(writer, "{} * {}", date, transaction.payee)?;
+            writeln!(writer, "  ; {}", transaction.narration)?;
+        }
+    }
+    for comment in &transaction.comments {
+        writeln!(writer, "  ; {}", comment)?;
+    }
+    for posting in &transaction.postings {
+        writeln!(writer, "{}", posting.format(format, base_currency))?;
+    }
+    writeln!(writer)
+}
+
+/// Sell trades of the wallet grouped by the index of the trade that sold the lots.
+fn sell_trades_per_trade(wallet: &Wallet) -> Vec<Vec<&SellTrade>> {
+    let mut sell_trades = vec![vec![]; wallet.trades.len()];
+    for st in wallet.sell_trades() {
+        sell_trades[st.sell_trade_idx].push(st);
+    }
+    sell_trades
+}
+
+/// Writes every trade, migration and fork of the wallet as a balanced transaction of a plain-text
+/// accounting journal.
+pub fn write_ledger<W: Write>(
+    mut writer: W,
+    wallet: &Wallet,
+    format: LedgerFormat,
+) -> io::Result<()> {
+    let sell_trades = sell_trades_per_trade(wallet);
+    let mut transactions: Vec<Transaction> = (0..wallet.trades.len())
+        .map(|i| transaction_for_trade(wallet, i, &sell_trades[i]))
+        .chain(wallet.migrations().iter().map(transaction_for_migration))
+        .chain(
+            wallet
+                .forks()
+                .iter()
+                .filter_map(|record| transaction_for_fork(wallet, record)),
+        )
+        .collect();
+    transactions.sort_by_key(|t| t.datetime);
+
+    if let (LedgerFormat::Beancount, Some(first)) = (format, transactions.first()) {
+        writeln!(
+            writer,
+            "option \"operating_currency\" \"{}\"",
+            wallet.base_currency()
+        )?;
+        writeln!(writer, "option \"infer_tolerance_from_cost\" \"TRUE\"")?;
+        writeln!(writer)?;
+        let accounts: BTreeSet<&str> = transactions
+            .iter()
+            .flat_map(|t| &t.postings)
+            .map(|p| p.account.as_str())
+            .collect();
+        let date = first.datetime.format(format.date_format());
+        for account in accounts {
+            writeln!(writer, "{} open {}", date, account)?;
+        }
+        writeln!(writer)?;
+    }
+
+    for transaction in &transactions {
+        write_transaction(&mut writer, transaction, format, wallet.base_currency())?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::wallet::WalletConfig;
+
+    fn trade(
+        dt: DateTime<Tz>,
+        volume_from: f64,
+        currency_from: &str,
+        currency_from_price_usd: f64,
+        volume_to: f64,
+        currency_to: &str,
+        currency_to_price_usd: f64,
+    ) -> Trade {
+        Trade {
+            datetime: dt,
+            exchange_name: "Exchange \"X\"".to_owned(),
+            volume_from,
+            currency_from: currency_from.to_owned(),
+            currency_from_price_usd,
+            volume_to,
+            currency_to: currency_to.to_owned(),
+            currency_to_price_usd,
+            notes: vec![],
+            source: None,
+        }
+    }
+
+    fn wallet() -> Wallet {
+        let mut wallet = Wallet::new(WalletConfig::default());
+        // Bought with fees, then partly exchanged for another coin within a year and partly sold
+        // for cash after a year.
+        let dt = UTC.ymd(2019, 3, 1).and_hms(10, 0, 0);
+        wallet.add_trade(trade(dt, 7070.0, "USD", 1.0, 1.0, "BTC", 7000.0));
+        let dt = UTC.ymd(2019, 7, 1).and_hms(10, 0, 0);
+        wallet.add_trade(trade(dt, 0.3, "BTC", 9100.0, 12.5, "ETH", 217.0));
+        let dt = UTC.ymd(2020, 6, 1).and_hms(10, 0, 0);
+        wallet.add_trade(trade(dt, 0.5, "BTC", 9000.0, 4455.0, "USD", 1.0));
+        wallet
+    }
+
+    /// Weight of a written posting as computed by beancount: at cost if it has one, else at price.
+    fn posting_weight(line: &str) -> f64 {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let volume: f64 = tokens[1].parse().unwrap();
+        if let Some(cost) = tokens.iter().find_map(|token| token.strip_prefix('{')) {
+            return volume * cost.parse::<f64>().unwrap();
+        }
+        match tokens.iter().position(|&token| token == "@") {
+            Some(i) => volume * tokens[i + 1].parse::<f64>().unwrap(),
+            None => volume,
+        }
+    }
+
+    #[test]
+    fn beancount_transactions_balance() {
+        let mut data = vec![];
+        write_ledger(&mut data, &wallet(), LedgerFormat::Beancount).unwrap();
+        let journal = String::from_utf8(data).unwrap();
+
+        let transactions: Vec<&str> = journal
+            .split("\n\n")
+            .filter(|block| block.contains(" * "))
+            .collect();
+        assert_eq!(transactions.len(), 3);
+        assert!(transactions[0].starts_with("2019-03-01 * \"Exchange 'X'\""));
+        for transaction in &transactions {
+            let postings: Vec<&str> = transaction
+                .lines()
+                .skip(1)
+                .filter(|line| !line.trim_start().starts_with(';'))
+                .collect();
+            assert!(postings.len() >= 2, "{}", transaction);
+            let balance: f64 = postings.iter().map(|line| posting_weight(line)).sum();
+            assert!(balance.abs() < 1e-6, "{}", transaction);
+        }
+        assert!(transactions[1].contains(ACCOUNT_GAINS_SHORT));
+        assert!(!transactions[1].contains(ACCOUNT_FEES));
+        assert!(transactions[2].contains(ACCOUNT_GAINS_LONG));
+        assert!(transactions[2].contains(ACCOUNT_FEES));
+    }
+
+    #[test]
+    fn gains_minus_fees_match_sell_trades() {
+        let wallet = wallet();
+        let sell_trades = sell_trades_per_trade(&wallet);
+        let postings = postings_for_trade(&wallet, 2, &sell_trades[2]);
+        let posted = |account: &str| -> f64 {
+            postings
+                .iter()
+                .filter(|p| p.account == account)
+                .map(|p| p.volume)
+                .sum()
+        };
+        let gain = -posted(ACCOUNT_GAINS_LONG) - posted(ACCOUNT_FEES);
+        assert_eq!(sell_trades[2].len(), 1);
+        assert!((gain - sell_trades[2][0].gain()).abs() < 1e-6);
+    }
+}
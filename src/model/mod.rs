@@ -1,6 +1,39 @@
+pub mod chart;
+pub mod duplicates;
+pub mod event;
+pub mod html;
+pub mod json;
+pub mod ledger;
+pub mod period;
+pub mod price_store;
+pub mod render;
 pub mod sell_trade;
+pub mod store;
+pub mod summary;
 pub mod trade;
 pub mod usd_trade;
+pub mod validation;
 pub mod wallet;
+pub mod what_if;
 
+use std::collections::HashMap;
+
+/// Currency code, e.g. `BTC`.
 pub type Currency = String;
+
+/// Renames the currencies of an event as per the alias table (e.g. XBT => BTC).
+pub fn apply_aliases(
+    aliases: &HashMap<Currency, Currency>,
+    currency_from: &mut Currency,
+    currency_to: &mut Currency,
+) {
+    for currency in [currency_from, currency_to] {
+        if let Some(alias_of) = aliases.get(currency.as_str()) {
+            *currency = alias_of.clone();
+        }
+    }
+}
+
+/// Fiat currency that all prices, cost basis and gains are expressed in unless configured otherwise.
+/// Fields and types named `*_usd` hold amounts in the configured base currency.
+pub const DEFAULT_BASE_CURRENCY: &str = "USD";
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use chrono_tz::Tz;
+
+use crate::utils::time_utils::start_of_date;
+
+/// How the target period is split into the sub-periods gains are summarized for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeriodScheme {
+    /// US estimated tax payment periods: Jan-Mar, Apr-May, Jun-Aug and Sep-Dec.
+    UsEstimated,
+    /// Calendar quarters.
+    Quarterly,
+    /// Calendar months.
+    Monthly,
+    /// Years starting on the given month and day (e.g. UK tax year on April 6).
+    FiscalYear { month: u32, day: u32 },
+}
+
+impl FromStr for PeriodScheme {
+    type Err = String;
+
+    /// Parses `us-estimated`, `quarterly`, `monthly`, `yearly`, `uk`, `au` or `fiscal:MM-DD`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Unknown period scheme: '{}'", s);
+        match s.to_lowercase().as_str() {
+            "us-estimated" => Ok(Self::UsEstimated),
+            "quarterly" => Ok(Self::Quarterly),
+            "monthly" => Ok(Self::Monthly),
+            "yearly" => Ok(Self::FiscalYear { month: 1, day: 1 }),
+            "uk" => Ok(Self::FiscalYear { month: 4, day: 6 }),
+            "au" => Ok(Self::FiscalYear { month: 7, day: 1 }),
+            s => {
+                let (month, day) = s
+                    .strip_prefix("fiscal:")
+                    .and_then(|s| s.split_once('-'))
+                    .ok_or_else(err)?;
+                let month: u32 = month.parse().map_err(|_| err())?;
+                let day: u32 = day.parse().map_err(|_| err())?;
+                // A non-leap year, as February 29 cannot start every fiscal year.
+                chrono::NaiveDate::from_ymd_opt(2001, month, day).ok_or_else(err)?;
+                Ok(Self::FiscalYear { month, day })
+            }
+        }
+    }
+}
+
+/// Named time period, `dt_from` inclusive and `dt_to` exclusive.
+pub struct Period {
+    pub name: String,
+    pub dt_from: DateTime<Tz>,
+    pub dt_to: DateTime<Tz>,
+}
+
+impl PeriodScheme {
+    /// Periods of the scheme overlapping the given time boundaries, clamped to them.
+    pub fn periods(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) -> Vec<Period> {
+        let tz = dt_from.timezone();
+        let date = |year: i32, month: u32, day: u32| {
+            start_of_date(&tz, NaiveDate::from_ymd(year, month, day))
+        };
+
+        // Unclamped periods of every year the boundaries may touch.
+        let mut periods = vec![];
+        for year in dt_from.year() - 1..=dt_to.year() {
+            match *self {
+                Self::UsEstimated | Self::Quarterly => {
+                    let months: &[u32] = if *self == Self::UsEstimated {
+                        &[1, 4, 6, 9]
+                    } else {
+                        &[1, 4, 7, 10]
+                    };
+                    for (i, &month) in months.iter().enumerate() {
+                        let dt_end = match months.get(i + 1) {
+                            Some(&next_month) => date(year, next_month, 1),
+                            None => date(year + 1, 1, 1),
+                        };
+                        periods.push(Period {
+                            name: format!("{} Q{}", year, i + 1),
+                            dt_from: date(year, month, 1),
+                            dt_to: dt_end,
+                        });
+                    }
+                }
+                Self::Monthly => {
+                    for month in 1..=12 {
+                        let dt_end = if month == 12 {
+                            date(year + 1, 1, 1)
+                        } else {
+                            date(year, month + 1, 1)
+                        };
+                        periods.push(Period {
+                            name: format!("{}-{:02}", year, month),
+                            dt_from: date(year, month, 1),
+                            dt_to: dt_end,
+                        });
+                    }
+                }
+                Self::FiscalYear { month, day } => {
+                    let name = if (month, day) == (1, 1) {
+                        year.to_string()
+                    } else {
+                        format!("{}/{:02}", year, (year + 1) % 100)
+                    };
+                    periods.push(Period {
+                        name,
+                        dt_from: date(year, month, day),
+                        dt_to: date(year + 1, month, day),
+                    });
+                }
+            }
+        }
+
+        periods
+            .into_iter()
+            .filter_map(|period| {
+                let dt_l = std::cmp::max(*dt_from, period.dt_from);
+                let dt_r = std::cmp::min(*dt_to, period.dt_to);
+                if dt_r <= dt_l {
+                    return None;
+                }
+                Some(Period {
+                    name: period.name,
+                    dt_from: dt_l,
+                    dt_to: dt_r,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::{Tz, UTC};
+
+    use super::*;
+
+    fn dt(year: i32, month: u32, day: u32) -> DateTime<Tz> {
+        UTC.ymd(year, month, day).and_hms(0, 0, 0)
+    }
+
+    fn bounds(periods: &[Period]) -> Vec<(&str, DateTime<Tz>, DateTime<Tz>)> {
+        periods
+            .iter()
+            .map(|p| (p.name.as_str(), p.dt_from, p.dt_to))
+            .collect()
+    }
+
+    #[test]
+    fn us_estimated_periods_of_a_year() {
+        let periods = PeriodScheme::UsEstimated.periods(&dt(2020, 1, 1), &dt(2021, 1, 1));
+        assert_eq!(
+            bounds(&periods),
+            vec![
+                ("2020 Q1", dt(2020, 1, 1), dt(2020, 4, 1)),
+                ("2020 Q2", dt(2020, 4, 1), dt(2020, 6, 1)),
+                ("2020 Q3", dt(2020, 6, 1), dt(2020, 9, 1)),
+                ("2020 Q4", dt(2020, 9, 1), dt(2021, 1, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn periods_are_clamped_to_the_boundaries() {
+        let periods = PeriodScheme::Quarterly.periods(&dt(2020, 2, 15), &dt(2020, 5, 10));
+        assert_eq!(
+            bounds(&periods),
+            vec![
+                ("2020 Q1", dt(2020, 2, 15), dt(2020, 4, 1)),
+                ("2020 Q2", dt(2020, 4, 1), dt(2020, 5, 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_periods_across_the_year_end() {
+        let periods = PeriodScheme::Monthly.periods(&dt(2020, 12, 1), &dt(2021, 2, 1));
+        assert_eq!(
+            bounds(&periods),
+            vec![
+                ("2020-12", dt(2020, 12, 1), dt(2021, 1, 1)),
+                ("2021-01", dt(2021, 1, 1), dt(2021, 2, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fiscal_years_start_in_the_previous_calendar_year() {
+        let scheme: PeriodScheme = "uk".parse().unwrap();
+        let periods = scheme.periods(&dt(2020, 1, 1), &dt(2021, 1, 1));
+        assert_eq!(
+            bounds(&periods),
+            vec![
+                ("2019/20", dt(2020, 1, 1), dt(2020, 4, 6)),
+                ("2020/21", dt(2020, 4, 6), dt(2021, 1, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn yearly_periods_are_named_by_year() {
+        let scheme: PeriodScheme = "yearly".parse().unwrap();
+        let periods = scheme.periods(&dt(2019, 6, 1), &dt(2020, 6, 1));
+        assert_eq!(
+            bounds(&periods),
+            vec![
+                ("2019", dt(2019, 6, 1), dt(2020, 1, 1)),
+                ("2020", dt(2020, 1, 1), dt(2020, 6, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fiscal_start_is_validated() {
+        assert_eq!(
+            "fiscal:02-28".parse::<PeriodScheme>(),
+            Ok(PeriodScheme::FiscalYear { month: 2, day: 28 })
+        );
+        assert!("fiscal:02-29".parse::<PeriodScheme>().is_err());
+        assert!("fiscal:13-01".parse::<PeriodScheme>().is_err());
+        assert!("fiscal:0401".parse::<PeriodScheme>().is_err());
+    }
+}
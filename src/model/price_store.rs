@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::{
+    model::{
+        event::{Event, LoadError},
+        trade::Source,
+        Currency,
+    },
+    utils::time_utils::{datetime_from_str, datetime_to_str},
+};
+
+/// How a price is picked from the historical prices of a currency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PriceLookup {
+    /// Price with the nearest timestamp, before or after.
+    Nearest,
+    /// Close price of the latest candle opened at or before the time.
+    Close,
+}
+
+impl FromStr for PriceLookup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "close" => Ok(Self::Close),
+            _ => Err(format!("Unknown price lookup: '{}'", s)),
+        }
+    }
+}
+
+struct PricePoint {
+    datetime: DateTime<Tz>,
+    price: f64,
+}
+
+/// Historical prices in the base currency loaded from local CSV files.
+/// Rows are either `currency,timestamp,price` or `currency,timestamp,open,high,low,close`,
+/// where timestamp is in any format accepted by `datetime_from_str`, e.g. Unix epoch seconds,
+/// and local times are in the timezone passed to `load`. A header row is optional: the first row
+/// is taken as the header if its timestamp does not parse.
+pub struct PriceStore {
+    prices: HashMap<Currency, Vec<PricePoint>>,
+    lookup: PriceLookup,
+    base_currency: Currency,
+    overrides: HashMap<Currency, f64>,
+}
+
+impl PriceStore {
+    pub fn new(lookup: PriceLookup, base_currency: &str) -> Self {
+        Self {
+            prices: HashMap::new(),
+            lookup,
+            base_currency: base_currency.to_owned(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn load(
+        filenames: &[String],
+        lookup: PriceLookup,
+        base_currency: &str,
+        tz: &Tz,
+    ) -> csv::Result<Self> {
+        let mut store = Self::new(lookup, base_currency);
+        for filename in filenames {
+            store.load_file(filename, tz)?;
+        }
+        Ok(store)
+    }
+
+    fn load_file(&mut self, filename: &str, tz: &Tz) -> csv::Result<()> {
+        self.read_prices(File::open(filename)?, filename, tz)
+    }
+
+    fn read_prices<R: io::Read>(&mut self, rdr: R, filename: &str, tz: &Tz) -> csv::Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(rdr);
+
+        for (idx, record) in reader.records().enumerate() {
+            let record = record?;
+            let is_header =
+                idx == 0 && record.len() > 1 && datetime_from_str(&record[1], tz).is_err();
+            if is_header {
+                continue;
+            }
+            let invalid_data = |message: String| {
+                let line = record.position().map_or(0, |position| position.line());
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", filename, line, message),
+                )
+            };
+            let price_idx = match record.len() {
+                3 => 2,
+                6 => 5,
+                n => return Err(invalid_data(format!("expected 3 or 6 columns, got {}", n)).into()),
+            };
+            let currency = record[0].to_owned();
+            let datetime =
+                datetime_from_str(&record[1], tz).map_err(|err| invalid_data(err.to_string()))?;
+            let price: f64 = record[price_idx]
+                .parse()
+                .map_err(|_| invalid_data(format!("Invalid number: '{}'", &record[price_idx])))?;
+            self.insert(currency, datetime, price);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, currency: Currency, datetime: DateTime<Tz>, price: f64) {
+        let points = self.prices.entry(currency).or_default();
+        let idx = points.partition_point(|p| p.datetime <= datetime);
+        points.insert(idx, PricePoint { datetime, price });
+    }
+
+    /// Sets the current price of the currency, used for valuation instead of historical prices.
+    pub fn set_override(&mut self, currency: Currency, price: f64) {
+        self.overrides.insert(currency, price);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty() && self.overrides.is_empty()
+    }
+
+    /// Price of the currency in the base currency at the given time, if known.
+    pub fn price(&self, currency: &str, dt: &DateTime<Tz>) -> Option<f64> {
+        if currency == self.base_currency {
+            return Some(1.0);
+        }
+        let points = self.prices.get(currency)?;
+        let idx = points.partition_point(|p| p.datetime <= *dt);
+        let before = idx.checked_sub(1).map(|i| &points[i]);
+        let point = match self.lookup {
+            PriceLookup::Close => before,
+            PriceLookup::Nearest => match (before, points.get(idx)) {
+                (Some(b), Some(a)) => {
+                    if *dt - b.datetime <= a.datetime - *dt {
+                        Some(b)
+                    } else {
+                        Some(a)
+                    }
+                }
+                (b, a) => b.or(a),
+            },
+        };
+        point.map(|p| p.price)
+    }
+
+    /// Price to value holdings with at the given time: the override if any, else historical.
+    pub fn valuation_price(&self, currency: &str, dt: &DateTime<Tz>) -> Option<f64> {
+        self.overrides
+            .get(currency)
+            .copied()
+            .or_else(|| self.price(currency, dt))
+    }
+
+    /// Fills prices missing in the event (NaN) from the store, or returns an error with the source
+    /// of the event if the store has no price of a currency at its time.
+    pub fn fill_missing_prices(&self, event: &mut Event) -> Result<(), LoadError> {
+        match event {
+            Event::Trade(trade) => {
+                if trade.currency_from_price_usd.is_nan() {
+                    trade.currency_from_price_usd =
+                        self.expect_price(&trade.currency_from, &trade.datetime, &trade.source)?;
+                }
+                if trade.currency_to_price_usd.is_nan() {
+                    trade.currency_to_price_usd =
+                        self.expect_price(&trade.currency_to, &trade.datetime, &trade.source)?;
+                }
+            }
+            Event::Fork(fork) => {
+                if fork.currency_to_price_usd.is_nan() {
+                    fork.currency_to_price_usd =
+                        self.expect_price(&fork.currency_to, &fork.datetime, &fork.source)?;
+                }
+            }
+            Event::Migration(_) => {}
+        }
+        Ok(())
+    }
+
+    fn expect_price(
+        &self,
+        currency: &str,
+        dt: &DateTime<Tz>,
+        source: &Option<Source>,
+    ) -> Result<f64, LoadError> {
+        self.price(currency, dt).ok_or_else(|| LoadError {
+            file: source
+                .as_ref()
+                .map_or_else(|| "<unknown>".to_owned(), |s| s.file.clone()),
+            line: source.as_ref().and_then(|s| s.line),
+            message: format!("No price of {} known at {}", currency, datetime_to_str(dt)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::trade::Trade;
+
+    fn trade_without_prices() -> Event {
+        Event::Trade(Trade {
+            datetime: UTC.ymd(2020, 1, 2).and_hms(0, 0, 0),
+            exchange_name: "Exchange".to_owned(),
+            volume_from: 100.0,
+            currency_from: "USD".to_owned(),
+            currency_from_price_usd: f64::NAN,
+            volume_to: 0.01,
+            currency_to: "BTC".to_owned(),
+            currency_to_price_usd: f64::NAN,
+            notes: vec![],
+            source: Some(Source {
+                file: "trades.txt".to_owned(),
+                line: Some(3),
+                importer: "text".to_owned(),
+                row_id: None,
+            }),
+        })
+    }
+
+    #[test]
+    fn header_row_is_optional() {
+        let rows = "BTC,2020-01-01 00:00:00,7000\nBTC,2020-01-02 00:00:00,7100,7200,6900,7150\n";
+        for data in &[
+            rows.to_owned(),
+            format!("currency,timestamp,price\n{}", rows),
+        ] {
+            let mut store = PriceStore::new(PriceLookup::Close, "USD");
+            store
+                .read_prices(data.as_bytes(), "prices.csv", &UTC)
+                .unwrap();
+            let dt = UTC.ymd(2020, 1, 1).and_hms(12, 0, 0);
+            assert_eq!(store.price("BTC", &dt), Some(7000.0));
+            let dt = UTC.ymd(2020, 1, 2).and_hms(12, 0, 0);
+            assert_eq!(store.price("BTC", &dt), Some(7150.0));
+        }
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_the_line() {
+        let data = "currency,timestamp,price\nBTC,2020-01-01 00:00:00,7000\nBTC,yesterday,7100\n";
+        let mut store = PriceStore::new(PriceLookup::Close, "USD");
+        let err = store
+            .read_prices(data.as_bytes(), "prices.csv", &UTC)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("prices.csv:3: "));
+    }
+
+    #[test]
+    fn missing_prices_are_filled() {
+        let mut store = PriceStore::new(PriceLookup::Close, "USD");
+        store.insert(
+            "BTC".to_owned(),
+            UTC.ymd(2020, 1, 1).and_hms(0, 0, 0),
+            7000.0,
+        );
+        let mut event = trade_without_prices();
+        store.fill_missing_prices(&mut event).unwrap();
+        match event {
+            Event::Trade(trade) => {
+                assert_eq!(trade.currency_from_price_usd, 1.0);
+                assert_eq!(trade.currency_to_price_usd, 7000.0);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn unknown_price_is_reported_with_the_source() {
+        let store = PriceStore::new(PriceLookup::Close, "USD");
+        let err = store
+            .fill_missing_prices(&mut trade_without_prices())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "trades.txt:3: No price of BTC known at 2020-01-02 00:00:00+00:00"
+        );
+    }
+}
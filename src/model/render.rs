@@ -0,0 +1,1109 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{
+    model::{
+        sell_trade::SellTrade,
+        summary::{
+            CurrencyHoldings, CurrencyUnrealizedGains, CurrencyValue, ForkIncome, FxGain,
+            GainsByTerm, LongTermLot, PeriodGains, SellTradeDetails, StablecoinComparison,
+            TaxLossHarvesting,
+        },
+        trade::{Source, Trade},
+        wallet::HoldingsItem,
+        what_if::{WhatIfResult, WhatIfSale},
+    },
+    utils::time_utils::datetime_to_str,
+};
+
+/// Output format of the reports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+    Markdown,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(Self::Text),
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "markdown" | "md" => Ok(Self::Markdown),
+            _ => Err(format!("Unknown output format: '{}'", s)),
+        }
+    }
+}
+
+/// Rows of a report for the tabular formats, values already formatted.
+pub struct Table {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Report data that can be written in every output format.
+pub trait Report {
+    /// Writes the report as the human-readable text printed by default.
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()>;
+
+    /// Flattens the report into rows for CSV and Markdown.
+    fn table(&self) -> Table;
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()>;
+}
+
+/// Writes the report in the given format.
+pub fn render(report: &dyn Report, format: OutputFormat, writer: &mut dyn Write) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => report.write_text(writer),
+        OutputFormat::Csv => write_csv_table(&report.table(), writer),
+        OutputFormat::Json => {
+            report.write_json(writer)?;
+            writeln!(writer)
+        }
+        OutputFormat::Markdown => write_markdown_table(&report.table(), writer),
+    }
+}
+
+/// Report written along with other reports, under its key in JSON and its title in the tabular
+/// formats.
+pub struct Section<'a> {
+    pub key: &'static str,
+    pub title: &'static str,
+    pub report: Box<dyn Report + 'a>,
+}
+
+/// Writes the reports one after another in the given format: text separated by blank lines, one
+/// JSON object with a field per report, or titled tables. A single report is written as by
+/// `render`.
+pub fn render_sections(
+    sections: &[Section],
+    format: OutputFormat,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    if let [section] = sections {
+        return render(section.report.as_ref(), format, writer);
+    }
+    match format {
+        OutputFormat::Text => {
+            for (i, section) in sections.iter().enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                section.report.write_text(writer)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => {
+            for (i, section) in sections.iter().enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "{}", section.title)?;
+                write_csv_table(&section.report.table(), writer)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            writeln!(writer, "{{")?;
+            for (i, section) in sections.iter().enumerate() {
+                let mut json = vec![];
+                section.report.write_json(&mut json)?;
+                // Indented one level deeper; line breaks only occur between JSON tokens.
+                let json = String::from_utf8_lossy(&json).replace('\n', "\n  ");
+                let separator = if i + 1 < sections.len() { "," } else { "" };
+                writeln!(writer, "  \"{}\": {}{}", section.key, json, separator)?;
+            }
+            writeln!(writer, "}}")
+        }
+        OutputFormat::Markdown => {
+            for (i, section) in sections.iter().enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "## {}", section.title)?;
+                writeln!(writer)?;
+                write_markdown_table(&section.report.table(), writer)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_csv_table(table: &Table, writer: &mut dyn Write) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    csv_writer.write_record(&table.columns)?;
+    for row in &table.rows {
+        csv_writer.write_record(row)?;
+    }
+    csv_writer.flush()
+}
+
+fn write_markdown_table(table: &Table, writer: &mut dyn Write) -> io::Result<()> {
+    let escape = |s: &str| s.replace('|', "\\|");
+    writeln!(writer, "| {} |", table.columns.join(" | "))?;
+    writeln!(writer, "|{}", " --- |".repeat(table.columns.len()))?;
+    for row in &table.rows {
+        let cells: Vec<String> = row.iter().map(|cell| escape(cell)).collect();
+        writeln!(writer, "| {} |", cells.join(" | "))?;
+    }
+    Ok(())
+}
+
+fn amount(x: f64) -> String {
+    format!("{:.9}", x)
+}
+
+fn optional_amount(x: Option<f64>) -> String {
+    x.map(amount).unwrap_or_default()
+}
+
+fn term_str(is_long_term: bool) -> String {
+    if is_long_term { "long" } else { "short" }.to_owned()
+}
+
+fn source_str(source: &Option<Source>) -> String {
+    source
+        .as_ref()
+        .map(|source| source.to_string())
+        .unwrap_or_default()
+}
+
+/// Trades in input order, with their notes if `print_notes` is set.
+pub struct TradesReport<'a> {
+    pub trades: Vec<&'a Trade>,
+    pub print_notes: bool,
+}
+
+impl Report for TradesReport<'_> {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for trade in &self.trades {
+            writeln!(writer, "{}", trade)?;
+            if self.print_notes {
+                for note in &trade.notes {
+                    writeln!(writer, "--- {}", note)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let mut columns = vec![
+            "datetime",
+            "exchange",
+            "volume_from",
+            "currency_from",
+            "currency_from_price",
+            "volume_to",
+            "currency_to",
+            "currency_to_price",
+            "source",
+        ];
+        if self.print_notes {
+            columns.push("notes");
+        }
+        let rows = self
+            .trades
+            .iter()
+            .map(|trade| {
+                let mut row = vec![
+                    datetime_to_str(&trade.datetime),
+                    trade.exchange_name.clone(),
+                    amount(trade.volume_from),
+                    trade.currency_from.clone(),
+                    amount(trade.currency_from_price_usd),
+                    amount(trade.volume_to),
+                    trade.currency_to.clone(),
+                    amount(trade.currency_to_price_usd),
+                    source_str(&trade.source),
+                ];
+                if self.print_notes {
+                    row.push(trade.notes.join("; "));
+                }
+                row
+            })
+            .collect();
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.trades)
+    }
+}
+
+/// Sell trades, followed by the sell and buy trades behind them if `full_info` is set.
+pub struct SellTradesReport<'a> {
+    pub sell_trades: Vec<SellTradeDetails<'a>>,
+    pub full_info: bool,
+}
+
+impl Report for SellTradesReport<'_> {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for (i, details) in self.sell_trades.iter().enumerate() {
+            writeln!(writer, "{}", details.sell_trade)?;
+            if !self.full_info {
+                continue;
+            }
+            writeln!(writer, "- SELL: {}", details.sell)?;
+            for note in &details.sell.notes {
+                writeln!(writer, "--- {}", note)?;
+            }
+            match details.buy {
+                Some(buy) => {
+                    writeln!(writer, "- BUY: {}", buy)?;
+                    for note in &buy.notes {
+                        writeln!(writer, "--- {}", note)?;
+                    }
+                }
+                None => writeln!(
+                    writer,
+                    "- FORK: {}",
+                    datetime_to_str(&details.sell_trade.buy_datetime)
+                )?,
+            }
+            if i + 1 < self.sell_trades.len() {
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "buy_datetime",
+            "sell_datetime",
+            "cost_basis",
+            "proceeds",
+            "gain",
+            "term",
+            "buy_source",
+            "sell_source",
+        ];
+        let rows = self
+            .sell_trades
+            .iter()
+            .map(|details| {
+                let st = details.sell_trade;
+                vec![
+                    st.currency.clone(),
+                    amount(st.volume),
+                    datetime_to_str(&st.buy_datetime),
+                    datetime_to_str(&st.sell_datetime),
+                    amount(st.cost_basis),
+                    amount(st.proceeds),
+                    amount(st.gain()),
+                    if st.is_long_term() { "long" } else { "short" }.to_owned(),
+                    source_str(&st.buy_source),
+                    source_str(&st.sell_source),
+                ]
+            })
+            .collect();
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        let sell_trades: Vec<_> = self.sell_trades.iter().map(|d| d.sell_trade).collect();
+        serde_json::to_writer_pretty(writer, &sell_trades)
+    }
+}
+
+/// Realized gains per period, term and currency.
+pub struct GainsReport {
+    pub periods: Vec<PeriodGains>,
+}
+
+impl Report for GainsReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for (i, period) in self.periods.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            for (gains, label) in &[(&period.long_term, "LONG"), (&period.short_term, "SHORT")] {
+                if *label == "SHORT" {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "{} {}-TERM gains:", period.name, label)?;
+                for info in &gains.currencies {
+                    writeln!(
+                        writer,
+                        "- {}: volume={:.9} proceeds={:.9} cost_basis={:.9} gains={:.9}",
+                        info.currency.as_deref().unwrap_or_default(),
+                        info.volume,
+                        info.proceeds,
+                        info.cost_basis,
+                        info.gain
+                    )?;
+                }
+                let totals = &gains.total;
+                writeln!(
+                    writer,
+                    "total_volume={:.9} total_proceeds={:.9} total_cost_basis={:.9} total_gains={:.9}",
+                    totals.volume, totals.proceeds, totals.cost_basis, totals.gain
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "period",
+            "term",
+            "currency",
+            "volume",
+            "proceeds",
+            "cost_basis",
+            "gain",
+        ];
+        let mut rows = vec![];
+        for period in &self.periods {
+            for (gains, term) in &[(&period.long_term, "long"), (&period.short_term, "short")] {
+                for info in gains.currencies.iter().chain(std::iter::once(&gains.total)) {
+                    rows.push(vec![
+                        period.name.clone(),
+                        term.to_string(),
+                        info.currency.clone().unwrap_or_else(|| "total".to_owned()),
+                        amount(info.volume),
+                        amount(info.proceeds),
+                        amount(info.cost_basis),
+                        amount(info.gain),
+                    ]);
+                }
+            }
+        }
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.periods)
+    }
+}
+
+/// Lots held per currency, one row per lot in the tabular formats.
+pub struct HoldingsReport {
+    pub holdings: Vec<CurrencyHoldings>,
+}
+
+impl Report for HoldingsReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for holdings in &self.holdings {
+            writeln!(
+                writer,
+                "{}: volume={:.9}, cost_basis={:.9}, avg_cost={:.9}",
+                holdings.currency, holdings.volume, holdings.cost_basis, holdings.avg_cost,
+            )?;
+            for lot in &holdings.lots {
+                writeln!(
+                    writer,
+                    "  - {:.9} {} (cost_basis={:.9}, price={:.9}, {})",
+                    lot.volume,
+                    holdings.currency,
+                    lot.cost_basis,
+                    lot.price_usd,
+                    datetime_to_str(&lot.datetime),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "cost_basis",
+            "price",
+            "datetime",
+            "source",
+        ];
+        let mut rows = vec![];
+        for holdings in &self.holdings {
+            for lot in &holdings.lots {
+                rows.push(vec![
+                    holdings.currency.clone(),
+                    amount(lot.volume),
+                    amount(lot.cost_basis),
+                    amount(lot.price_usd),
+                    datetime_to_str(&lot.datetime),
+                    source_str(&lot.source),
+                ]);
+            }
+        }
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.holdings)
+    }
+}
+
+/// Forks of the period with the volume received and the income recognized.
+pub struct ForksReport {
+    pub forks: Vec<ForkIncome>,
+}
+
+impl ForksReport {
+    fn total_income(&self) -> f64 {
+        self.forks.iter().map(|fork| fork.income).sum()
+    }
+}
+
+impl Report for ForksReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Forks:")?;
+        for fork in &self.forks {
+            writeln!(
+                writer,
+                "{} {} => {} (1:{}): volume={:.9} price={:.9} income={:.9}",
+                datetime_to_str(&fork.datetime),
+                fork.currency_from,
+                fork.currency_to,
+                fork.ratio,
+                fork.volume,
+                fork.price,
+                fork.income,
+            )?;
+        }
+        writeln!(writer, "total_income={:.9}", self.total_income())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "datetime",
+            "currency_from",
+            "currency_to",
+            "ratio",
+            "volume",
+            "price",
+            "income",
+        ];
+        let mut rows: Vec<Vec<String>> = self
+            .forks
+            .iter()
+            .map(|fork| {
+                vec![
+                    datetime_to_str(&fork.datetime),
+                    fork.currency_from.clone(),
+                    fork.currency_to.clone(),
+                    fork.ratio.to_string(),
+                    amount(fork.volume),
+                    amount(fork.price),
+                    amount(fork.income),
+                ]
+            })
+            .collect();
+        let mut total = vec![String::new(); columns.len()];
+        total[0] = "total".to_owned();
+        total[6] = amount(self.total_income());
+        rows.push(total);
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.forks)
+    }
+}
+
+/// Disposals of foreign cash and their currency gains, exempt ones marked.
+pub struct FxGainsReport<'a> {
+    pub fx_gains: Vec<FxGain<'a>>,
+}
+
+impl FxGainsReport<'_> {
+    /// Total and exempt gains.
+    fn totals(&self) -> (f64, f64) {
+        let mut total_gain = 0.0;
+        let mut exempt_gain = 0.0;
+        for fx_gain in &self.fx_gains {
+            total_gain += fx_gain.sell_trade.gain();
+            if fx_gain.exempt {
+                exempt_gain += fx_gain.sell_trade.gain();
+            }
+        }
+        (total_gain, exempt_gain)
+    }
+}
+
+impl Report for FxGainsReport<'_> {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Currency gains:")?;
+        for fx_gain in &self.fx_gains {
+            if fx_gain.exempt {
+                writeln!(writer, "{} (exempt)", fx_gain.sell_trade)?;
+            } else {
+                writeln!(writer, "{}", fx_gain.sell_trade)?;
+            }
+        }
+        let (total_gain, exempt_gain) = self.totals();
+        writeln!(
+            writer,
+            "total_gains={:.9} exempt_gains={:.9} taxable_gains={:.9}",
+            total_gain,
+            exempt_gain,
+            total_gain - exempt_gain
+        )
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "buy_datetime",
+            "sell_datetime",
+            "cost_basis",
+            "proceeds",
+            "gain",
+            "exempt",
+        ];
+        let mut rows: Vec<Vec<String>> = self
+            .fx_gains
+            .iter()
+            .map(|fx_gain| {
+                let st = fx_gain.sell_trade;
+                vec![
+                    st.currency.clone(),
+                    amount(st.volume),
+                    datetime_to_str(&st.buy_datetime),
+                    datetime_to_str(&st.sell_datetime),
+                    amount(st.cost_basis),
+                    amount(st.proceeds),
+                    amount(st.gain()),
+                    fx_gain.exempt.to_string(),
+                ]
+            })
+            .collect();
+        let (total_gain, exempt_gain) = self.totals();
+        for (label, gain) in &[
+            ("total", total_gain),
+            ("exempt", exempt_gain),
+            ("taxable", total_gain - exempt_gain),
+        ] {
+            let mut row = vec![String::new(); columns.len()];
+            row[0] = label.to_string();
+            row[6] = amount(*gain);
+            rows.push(row);
+        }
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.fx_gains)
+    }
+}
+
+/// Realized gains of the period with the stablecoins treated as cash and as property.
+pub struct StablecoinPoliciesReport {
+    pub comparison: StablecoinComparison,
+}
+
+impl Report for StablecoinPoliciesReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Stablecoin policies ({}):",
+            self.comparison.stablecoins.join(", ")
+        )?;
+        for policy in &self.comparison.policies {
+            writeln!(
+                writer,
+                "- {}: short_term_gains={:.9} long_term_gains={:.9} total_gains={:.9}",
+                policy.label,
+                policy.gains.short_term,
+                policy.gains.long_term,
+                policy.gains.total()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "policy",
+            "short_term_gains",
+            "long_term_gains",
+            "total_gains",
+        ];
+        let rows = self
+            .comparison
+            .policies
+            .iter()
+            .map(|policy| {
+                vec![
+                    policy.label.clone(),
+                    amount(policy.gains.short_term),
+                    amount(policy.gains.long_term),
+                    amount(policy.gains.total()),
+                ]
+            })
+            .collect();
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.comparison)
+    }
+}
+
+/// Volume and market value of the holdings per currency at `dt`.
+pub struct HoldingsValueReport {
+    pub dt: DateTime<Tz>,
+    pub values: Vec<CurrencyValue>,
+}
+
+impl HoldingsValueReport {
+    /// Value of the currencies with a known price.
+    fn total_value(&self) -> f64 {
+        self.values.iter().filter_map(|value| value.value).sum()
+    }
+}
+
+impl Report for HoldingsValueReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Holdings value at {}:", datetime_to_str(&self.dt))?;
+        for value in &self.values {
+            match (value.price, value.value) {
+                (Some(price), Some(currency_value)) => writeln!(
+                    writer,
+                    "- {}: volume={:.9} price={:.9} value={:.9}",
+                    value.currency, value.volume, price, currency_value
+                )?,
+                _ => writeln!(
+                    writer,
+                    "- {}: volume={:.9} price=unknown",
+                    value.currency, value.volume
+                )?,
+            }
+        }
+        writeln!(writer, "total_value={:.9}", self.total_value())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec!["currency", "volume", "price", "value"];
+        let mut rows: Vec<Vec<String>> = self
+            .values
+            .iter()
+            .map(|value| {
+                vec![
+                    value.currency.clone(),
+                    amount(value.volume),
+                    optional_amount(value.price),
+                    optional_amount(value.value),
+                ]
+            })
+            .collect();
+        rows.push(vec![
+            "total".to_owned(),
+            String::new(),
+            String::new(),
+            amount(self.total_value()),
+        ]);
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.values)
+    }
+}
+
+/// Unrealized gains of the lots held at `dt` per currency, one row per lot in the tabular
+/// formats.
+pub struct UnrealizedGainsReport {
+    pub dt: DateTime<Tz>,
+    pub currencies: Vec<CurrencyUnrealizedGains>,
+}
+
+impl UnrealizedGainsReport {
+    /// Value, cost basis and gains of the currencies with a known price.
+    fn totals(&self) -> (f64, f64, GainsByTerm) {
+        let mut value = 0.0;
+        let mut cost_basis = 0.0;
+        let mut gains = GainsByTerm::default();
+        for info in &self.currencies {
+            value += info.value;
+            cost_basis += info.cost_basis;
+            gains.short_term += info.gains.short_term;
+            gains.long_term += info.gains.long_term;
+        }
+        (value, cost_basis, gains)
+    }
+}
+
+impl Report for UnrealizedGainsReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "Unrealized gains at {}:", datetime_to_str(&self.dt))?;
+        for info in &self.currencies {
+            let price = match info.price {
+                Some(price) => price,
+                None => {
+                    writeln!(
+                        writer,
+                        "{}: volume={:.9} price=unknown",
+                        info.currency, info.volume
+                    )?;
+                    continue;
+                }
+            };
+            writeln!(
+                writer,
+                "{}: volume={:.9} price={:.9} value={:.9} cost_basis={:.9} short_term_gains={:.9} long_term_gains={:.9}",
+                info.currency,
+                info.volume,
+                price,
+                info.value,
+                info.cost_basis,
+                info.gains.short_term,
+                info.gains.long_term
+            )?;
+            for lot in &info.lots {
+                writeln!(
+                    writer,
+                    "  - {:.9} {} (value={:.9}, cost_basis={:.9}, gain={:.9}, {}, {})",
+                    lot.lot.volume,
+                    info.currency,
+                    lot.value,
+                    lot.lot.cost_basis,
+                    lot.gain,
+                    if lot.long_term { "LONG" } else { "SHORT" },
+                    datetime_to_str(&lot.lot.datetime),
+                )?;
+            }
+        }
+        let (value, cost_basis, gains) = self.totals();
+        writeln!(
+            writer,
+            "total_value={:.9} total_cost_basis={:.9} short_term_gains={:.9} long_term_gains={:.9} total_gains={:.9}",
+            value,
+            cost_basis,
+            gains.short_term,
+            gains.long_term,
+            gains.total()
+        )
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "price",
+            "value",
+            "cost_basis",
+            "gain",
+            "term",
+            "datetime",
+        ];
+        let mut rows = vec![];
+        for info in &self.currencies {
+            if info.price.is_none() {
+                let mut row = vec![String::new(); columns.len()];
+                row[0] = info.currency.clone();
+                row[1] = amount(info.volume);
+                rows.push(row);
+            }
+            for lot in &info.lots {
+                rows.push(vec![
+                    info.currency.clone(),
+                    amount(lot.lot.volume),
+                    optional_amount(info.price),
+                    amount(lot.value),
+                    amount(lot.lot.cost_basis),
+                    amount(lot.gain),
+                    term_str(lot.long_term),
+                    datetime_to_str(&lot.lot.datetime),
+                ]);
+            }
+        }
+        let (value, cost_basis, gains) = self.totals();
+        rows.push(vec![
+            "total".to_owned(),
+            String::new(),
+            String::new(),
+            amount(value),
+            amount(cost_basis),
+            amount(gains.total()),
+            String::new(),
+            String::new(),
+        ]);
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.currencies)
+    }
+}
+
+/// Lots held at `dt` with the time their sale becomes long-term, soonest first.
+pub struct LongTermCalendarReport {
+    pub dt: DateTime<Tz>,
+    pub lots: Vec<LongTermLot>,
+}
+
+impl Report for LongTermCalendarReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Long-term calendar at {}:",
+            datetime_to_str(&self.dt)
+        )?;
+        for lot in &self.lots {
+            let gain = match lot.gain {
+                Some(gain) => format!("{:.9}", gain),
+                None => "unknown".to_owned(),
+            };
+            writeln!(
+                writer,
+                "- {:.9} {} (cost_basis={:.9}, gain={}, bought {}, long-term {}, days_left={})",
+                lot.lot.volume,
+                lot.currency,
+                lot.lot.cost_basis,
+                gain,
+                datetime_to_str(&lot.lot.datetime),
+                datetime_to_str(&lot.dt_long_term),
+                lot.days_left,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "cost_basis",
+            "gain",
+            "datetime",
+            "long_term_datetime",
+            "days_left",
+        ];
+        let rows = self
+            .lots
+            .iter()
+            .map(|lot| {
+                vec![
+                    lot.currency.clone(),
+                    amount(lot.lot.volume),
+                    amount(lot.lot.cost_basis),
+                    optional_amount(lot.gain),
+                    datetime_to_str(&lot.lot.datetime),
+                    datetime_to_str(&lot.dt_long_term),
+                    lot.days_left.to_string(),
+                ]
+            })
+            .collect();
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.lots)
+    }
+}
+
+/// Lots that would realize a loss if sold at `dt` and the effect on year-to-date gains.
+pub struct TaxLossHarvestingReport {
+    pub dt: DateTime<Tz>,
+    pub harvesting: TaxLossHarvesting,
+}
+
+impl TaxLossHarvestingReport {
+    fn summary(&self) -> [(&'static str, GainsByTerm); 3] {
+        [
+            ("realized year-to-date", self.harvesting.realized),
+            ("harvestable losses", self.harvesting.harvestable),
+            ("after harvesting", self.harvesting.after_harvesting()),
+        ]
+    }
+}
+
+impl Report for TaxLossHarvestingReport {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Tax-loss harvesting at {}:",
+            datetime_to_str(&self.dt)
+        )?;
+        for info in &self.harvesting.losses {
+            writeln!(
+                writer,
+                "- {:.9} {} (price={:.9}, cost_basis={:.9}, loss={:.9}, {}, {})",
+                info.lot.volume,
+                info.currency,
+                info.price,
+                info.lot.cost_basis,
+                info.loss,
+                if info.long_term { "LONG" } else { "SHORT" },
+                datetime_to_str(&info.lot.datetime),
+            )?;
+        }
+        for (label, gains) in &self.summary() {
+            writeln!(
+                writer,
+                "{}: short_term_gains={:.9} long_term_gains={:.9} total_gains={:.9}",
+                label,
+                gains.short_term,
+                gains.long_term,
+                gains.total()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "price",
+            "cost_basis",
+            "gain",
+            "term",
+            "datetime",
+        ];
+        let mut rows: Vec<Vec<String>> = self
+            .harvesting
+            .losses
+            .iter()
+            .map(|info| {
+                vec![
+                    info.currency.clone(),
+                    amount(info.lot.volume),
+                    amount(info.price),
+                    amount(info.lot.cost_basis),
+                    amount(info.loss),
+                    term_str(info.long_term),
+                    datetime_to_str(&info.lot.datetime),
+                ]
+            })
+            .collect();
+        for (label, gains) in &self.summary() {
+            for (gain, is_long_term) in &[(gains.short_term, false), (gains.long_term, true)] {
+                let mut row = vec![String::new(); columns.len()];
+                row[0] = label.to_string();
+                row[4] = amount(*gain);
+                row[5] = term_str(*is_long_term);
+                rows.push(row);
+            }
+        }
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.harvesting)
+    }
+}
+
+/// Sell trades a hypothetical sale would generate, its gains and the lots left of the currency.
+pub struct WhatIfReport<'a> {
+    pub result: &'a WhatIfResult,
+}
+
+impl WhatIfReport<'_> {
+    fn remaining_lots(&self) -> &[HoldingsItem] {
+        let result = self.result;
+        result
+            .wallet
+            .holdings()
+            .get(&result.sale.currency)
+            .map_or(&[], |bucket| bucket.as_slice())
+    }
+}
+
+impl Report for WhatIfReport<'_> {
+    fn write_text(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let result = self.result;
+        let sale = &result.sale;
+        writeln!(
+            writer,
+            "What-if sale of {:.9} {} at {:.9} on {} ({:?}):",
+            sale.volume,
+            sale.currency,
+            sale.price_usd,
+            datetime_to_str(&sale.datetime),
+            sale.lot_method
+        )?;
+        for st in &result.sell_trades {
+            writeln!(writer, "{}", st)?;
+        }
+        writeln!(
+            writer,
+            "short_term_gains={:.9} long_term_gains={:.9} total_gains={:.9}",
+            result.gain_st,
+            result.gain_lt,
+            result.gain_st + result.gain_lt
+        )?;
+        writeln!(writer, "Remaining {}:", sale.currency)?;
+        for item in self.remaining_lots() {
+            writeln!(
+                writer,
+                "  - {:.9} {} (cost_basis={:.9}, {})",
+                item.volume,
+                sale.currency,
+                item.cost_basis,
+                datetime_to_str(&item.datetime),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> Table {
+        let columns = vec![
+            "currency",
+            "volume",
+            "buy_datetime",
+            "sell_datetime",
+            "cost_basis",
+            "proceeds",
+            "gain",
+            "term",
+        ];
+        let mut rows: Vec<Vec<String>> = self
+            .result
+            .sell_trades
+            .iter()
+            .map(|st| {
+                vec![
+                    st.currency.clone(),
+                    amount(st.volume),
+                    datetime_to_str(&st.buy_datetime),
+                    datetime_to_str(&st.sell_datetime),
+                    amount(st.cost_basis),
+                    amount(st.proceeds),
+                    amount(st.gain()),
+                    term_str(st.is_long_term()),
+                ]
+            })
+            .collect();
+        let mut total = vec![String::new(); columns.len()];
+        total[0] = "total".to_owned();
+        total[6] = amount(self.result.gain_st + self.result.gain_lt);
+        rows.push(total);
+        Table { columns, rows }
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        struct WhatIfJson<'a> {
+            sale: &'a WhatIfSale,
+            sell_trades: &'a [SellTrade],
+            short_term_gains: f64,
+            long_term_gains: f64,
+            remaining_lots: &'a [HoldingsItem],
+        }
+        let json = WhatIfJson {
+            sale: &self.result.sale,
+            sell_trades: &self.result.sell_trades,
+            short_term_gains: self.result.gain_st,
+            long_term_gains: self.result.gain_lt,
+            remaining_lots: self.remaining_lots(),
+        };
+        serde_json::to_writer_pretty(writer, &json)
+    }
+}
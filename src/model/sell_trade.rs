@@ -1,43 +1,56 @@
 use std::fmt;
 
-use chrono::{DateTime, Datelike, Duration};
+use chrono::DateTime;
 use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    model::{trade::Trade, Currency},
-    utils::time_utils::{datetime_to_str, start_of_the_day},
+    model::{
+        trade::{Source, Trade},
+        wallet::HoldingsItem,
+        Currency,
+    },
+    utils::time_utils::{datetime_to_str, long_term_datetime, serde_datetime},
 };
 
 /// Sell trade information for Form 8949.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SellTrade {
     pub volume: f64,
     pub currency: Currency,
-    pub cost_basis: f64, // volume * price_bought_usd + fees
-    pub proceeds: f64,   // volume * price_sold_usd - fees (0 if crypto-to-crypto)
-    pub buy_trade_idx: usize,
+    pub cost_basis: f64,              // volume * price_bought_usd + fees
+    pub proceeds: f64,                // volume * price_sold_usd - fees (0 if crypto-to-crypto)
+    pub buy_trade_idx: Option<usize>, // None if acquired by a fork
     pub sell_trade_idx: usize,
-    buy_datetime: DateTime<Tz>,
+    #[serde(with = "serde_datetime")]
+    pub buy_datetime: DateTime<Tz>,
+    #[serde(with = "serde_datetime")]
     pub sell_datetime: DateTime<Tz>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buy_source: Option<Source>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sell_source: Option<Source>,
 }
 
 impl SellTrade {
     pub fn new(
         volume: f64,
         cost_basis: f64,
-        buy_trade: &Trade,
+        lot: &HoldingsItem,
         sell_trade: &Trade,
-        buy_trade_idx: usize,
         sell_trade_idx: usize,
+        is_sold_for_cash: bool,
     ) -> Self {
-        let fees = if sell_trade.currency_to == "USD" {
+        let fees = if is_sold_for_cash {
             (volume / sell_trade.volume_from) * sell_trade.fees_usd()
         } else {
             0.0
         };
         let proceeds = volume * sell_trade.currency_from_price_usd - fees;
         let currency = sell_trade.currency_from.clone();
-        let buy_datetime = buy_trade.datetime.clone();
-        let sell_datetime = sell_trade.datetime.clone();
+        let buy_trade_idx = lot.trade_idx;
+        let buy_datetime = lot.datetime;
+        let sell_datetime = sell_trade.datetime;
         Self {
             volume,
             currency,
@@ -47,19 +60,17 @@ impl SellTrade {
             sell_trade_idx,
             buy_datetime,
             sell_datetime,
+            buy_source: lot.source.clone(),
+            sell_source: sell_trade.source.clone(),
         }
     }
 
+    /// Whether the lot was held for more than one year.
     pub fn is_long_term(&self) -> bool {
-        let mut dt = start_of_the_day(&self.buy_datetime) + Duration::days(1);
-        if dt.month() == 2 && dt.day() == 29 {
-            // leap_year/02/29 is a special case.
-            dt = dt + Duration::days(1);
-        }
-        let dt_can_sell_lt = dt.with_year(dt.year() + 1).unwrap();
-        self.sell_datetime >= dt_can_sell_lt
+        self.sell_datetime >= long_term_datetime(&self.buy_datetime)
     }
 
+    /// Realized gain, negative for a loss.
     pub fn gain(&self) -> f64 {
         self.proceeds - self.cost_basis
     }
@@ -0,0 +1,334 @@
+use chrono_tz::Tz;
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Transaction};
+
+use crate::{
+    model::{
+        event::Event,
+        trade::{missing_price, Source, Trade},
+    },
+    utils::time_utils::{datetime_from_str, datetime_to_precise_str},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    fingerprint TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL,
+    timestamp_ms INTEGER NOT NULL,
+    datetime TEXT NOT NULL,
+    data TEXT
+);
+CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp_ms, id);
+CREATE TABLE IF NOT EXISTS trades (
+    event_id INTEGER PRIMARY KEY REFERENCES events (id),
+    exchange_name TEXT NOT NULL,
+    volume_from REAL NOT NULL,
+    currency_from TEXT NOT NULL,
+    currency_from_price_usd REAL,
+    volume_to REAL NOT NULL,
+    currency_to TEXT NOT NULL,
+    currency_to_price_usd REAL,
+    source_file TEXT,
+    source_line INTEGER,
+    source_importer TEXT,
+    source_row_id TEXT
+);
+CREATE TABLE IF NOT EXISTS notes (
+    event_id INTEGER NOT NULL REFERENCES events (id),
+    position INTEGER NOT NULL,
+    note TEXT NOT NULL,
+    PRIMARY KEY (event_id, position)
+);
+";
+
+/// Numbers of events added and skipped by an import.
+pub struct ImportSummary {
+    pub added: usize,
+    /// Events with a fingerprint already stored.
+    pub skipped: usize,
+}
+
+/// SQLite file holding imported events with their notes, so that exchange exports can be imported
+/// incrementally and read back without parsing them again.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the database file, creating it and its tables if needed.
+    pub fn open(filename: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(filename)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Replaces the notes of stored events with the ones of `updated`, matched by fingerprint, and
+    /// adds the events whose fingerprint is not stored yet, in one transaction.
+    pub fn import(
+        &mut self,
+        updated: &[Event],
+        events: &[Event],
+    ) -> rusqlite::Result<ImportSummary> {
+        let tx = self.conn.transaction()?;
+        for event in updated {
+            let id: i64 = tx.query_row(
+                "SELECT id FROM events WHERE fingerprint = ?1",
+                [event.fingerprint()],
+                |row| row.get(0),
+            )?;
+            tx.execute("DELETE FROM notes WHERE event_id = ?1", [id])?;
+            insert_notes(&tx, id, event.notes())?;
+        }
+        let mut summary = ImportSummary {
+            added: 0,
+            skipped: 0,
+        };
+        for event in events {
+            if insert_event(&tx, event)? {
+                summary.added += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// All stored events in chronological order, events at the same time in import order, with
+    /// datetimes in `tz`.
+    pub fn load_events(&self, tz: &Tz) -> rusqlite::Result<Vec<Event>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT e.id, e.kind, e.datetime, e.data,
+                    t.exchange_name, t.volume_from, t.currency_from, t.currency_from_price_usd,
+                    t.volume_to, t.currency_to, t.currency_to_price_usd,
+                    t.source_file, t.source_line, t.source_importer, t.source_row_id
+             FROM events e LEFT JOIN trades t ON t.event_id = e.id
+             ORDER BY e.timestamp_ms, e.id",
+        )?;
+        let mut notes_stmt = self
+            .conn
+            .prepare("SELECT note FROM notes WHERE event_id = ?1 ORDER BY position")?;
+
+        let mut events = vec![];
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+            let notes = notes_stmt
+                .query_map([id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            let mut event = if kind == "trade" {
+                let datetime: String = row.get(2)?;
+                let source_file: Option<String> = row.get(11)?;
+                let source_line: Option<usize> = row.get(12)?;
+                let source_importer: Option<String> = row.get(13)?;
+                let source_row_id: Option<String> = row.get(14)?;
+                Event::Trade(Trade {
+                    datetime: datetime_from_str(&datetime, tz).map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(2, Type::Text, Box::new(err))
+                    })?,
+                    exchange_name: row.get(4)?,
+                    volume_from: row.get(5)?,
+                    currency_from: row.get(6)?,
+                    // NaN is stored as NULL.
+                    currency_from_price_usd: row
+                        .get::<_, Option<f64>>(7)?
+                        .unwrap_or_else(missing_price),
+                    volume_to: row.get(8)?,
+                    currency_to: row.get(9)?,
+                    currency_to_price_usd: row
+                        .get::<_, Option<f64>>(10)?
+                        .unwrap_or_else(missing_price),
+                    notes: vec![],
+                    source: source_file.map(|file| Source {
+                        file,
+                        line: source_line,
+                        importer: source_importer.unwrap_or_default(),
+                        row_id: source_row_id,
+                    }),
+                })
+            } else {
+                let data: String = row.get(3)?;
+                let mut event: Event = serde_json::from_str(&data).map_err(|err| {
+                    rusqlite::Error::FromSqlConversionFailure(3, Type::Text, Box::new(err))
+                })?;
+                let datetime = event.datetime().with_timezone(tz);
+                *event.datetime_mut() = datetime;
+                event
+            };
+            *event.notes_mut() = notes;
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+/// Inserts the event unless its fingerprint is already stored, returning whether it was.
+fn insert_event(tx: &Transaction, event: &Event) -> rusqlite::Result<bool> {
+    let fingerprint = event.fingerprint();
+    let exists = tx
+        .query_row(
+            "SELECT 1 FROM events WHERE fingerprint = ?1",
+            [&fingerprint],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if exists {
+        return Ok(false);
+    }
+
+    let (kind, data) = match event {
+        Event::Trade(_) => ("trade", None),
+        Event::Migration(_) => ("migration", Some(event_data(event))),
+        Event::Fork(_) => ("fork", Some(event_data(event))),
+    };
+    let datetime = event.datetime();
+    tx.execute(
+        "INSERT INTO events (fingerprint, kind, timestamp_ms, datetime, data)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            fingerprint,
+            kind,
+            datetime.timestamp_millis(),
+            datetime_to_precise_str(datetime),
+            data
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+
+    if let Event::Trade(trade) = event {
+        tx.execute(
+            "INSERT INTO trades (event_id, exchange_name, volume_from, currency_from,
+                currency_from_price_usd, volume_to, currency_to, currency_to_price_usd, source_file,
+                source_line, source_importer, source_row_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                id,
+                trade.exchange_name,
+                trade.volume_from,
+                trade.currency_from,
+                trade.currency_from_price_usd,
+                trade.volume_to,
+                trade.currency_to,
+                trade.currency_to_price_usd,
+                trade.source.as_ref().map(|source| &source.file),
+                trade.source.as_ref().and_then(|source| source.line),
+                trade.source.as_ref().map(|source| &source.importer),
+                trade
+                    .source
+                    .as_ref()
+                    .and_then(|source| source.row_id.as_ref()),
+            ],
+        )?;
+    }
+    insert_notes(tx, id, event.notes())?;
+    Ok(true)
+}
+
+fn insert_notes(tx: &Transaction, event_id: i64, notes: &[String]) -> rusqlite::Result<()> {
+    for (position, note) in notes.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO notes (event_id, position, note) VALUES (?1, ?2, ?3)",
+            params![event_id, position, note],
+        )?;
+    }
+    Ok(())
+}
+
+/// JSON of a migration or fork without its notes, which are stored in their own table.
+fn event_data(event: &Event) -> String {
+    let mut event = event.clone();
+    event.notes_mut().clear();
+    serde_json::to_string(&event).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::{Tz, UTC};
+
+    use super::*;
+    use crate::model::event::Fork;
+
+    fn events() -> Vec<Event> {
+        let trade = |day: u32, volume_to: f64| {
+            Event::Trade(Trade {
+                datetime: UTC.ymd(2020, 1, day).and_hms(0, 0, 0),
+                exchange_name: "Exchange".to_owned(),
+                volume_from: 7000.0,
+                currency_from: "USD".to_owned(),
+                currency_from_price_usd: 1.0,
+                volume_to,
+                currency_to: "BTC".to_owned(),
+                currency_to_price_usd: f64::NAN,
+                notes: vec!["note".to_owned()],
+                source: Some(Source {
+                    file: "trades.txt".to_owned(),
+                    line: Some(day as usize),
+                    importer: "text".to_owned(),
+                    row_id: None,
+                }),
+            })
+        };
+        vec![
+            trade(1, 1.0),
+            Event::Fork(Fork {
+                datetime: UTC.ymd(2020, 1, 2).and_hms(0, 0, 0),
+                currency_from: "BTC".to_owned(),
+                currency_to: "BCH".to_owned(),
+                ratio: 1.0,
+                currency_to_price_usd: 300.0,
+                notes: vec![],
+                source: None,
+            }),
+            trade(3, 0.5),
+        ]
+    }
+
+    fn fingerprints(events: &[Event]) -> Vec<String> {
+        events.iter().map(|event| event.fingerprint()).collect()
+    }
+
+    #[test]
+    fn import_is_idempotent() {
+        let mut store = Store::open(":memory:").unwrap();
+        let summary = store.import(&[], &events()).unwrap();
+        assert_eq!((summary.added, summary.skipped), (3, 0));
+        let summary = store.import(&[], &events()).unwrap();
+        assert_eq!((summary.added, summary.skipped), (0, 3));
+
+        let stored = store.load_events(&UTC).unwrap();
+        assert_eq!(fingerprints(&stored), fingerprints(&events()));
+        assert_eq!(stored[0].notes(), &["note".to_owned()]);
+        assert_eq!(stored[0].source(), events()[0].source());
+        match &stored[0] {
+            Event::Trade(trade) => assert!(trade.currency_to_price_usd.is_nan()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn import_updates_notes_of_stored_events() {
+        let mut store = Store::open(":memory:").unwrap();
+        store.import(&[], &events()).unwrap();
+        let mut updated = events();
+        updated[0].notes_mut().push("merged".to_owned());
+        store.import(&updated[..1], &[]).unwrap();
+
+        let stored = store.load_events(&UTC).unwrap();
+        assert_eq!(stored[0].notes(), &["note".to_owned(), "merged".to_owned()]);
+        assert_eq!(stored[2].notes(), &["note".to_owned()]);
+    }
+
+    #[test]
+    fn events_are_loaded_in_the_timezone() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        let mut store = Store::open(":memory:").unwrap();
+        store.import(&[], &events()).unwrap();
+        for (stored, event) in store.load_events(&tz).unwrap().iter().zip(&events()) {
+            assert_eq!(stored.datetime().timezone(), tz);
+            assert_eq!(stored.datetime(), event.datetime());
+        }
+    }
+}
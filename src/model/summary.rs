@@ -0,0 +1,219 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{
+    model::{sell_trade::SellTrade, trade::Trade, wallet::HoldingsItem, Currency},
+    utils::time_utils::serde_datetime,
+};
+
+/// Sell trades of a currency, or of all currencies for totals, summed up.
+#[derive(Clone, Default, Serialize)]
+pub struct CurrencyGains {
+    pub currency: Option<Currency>, // None for totals
+    pub volume: f64,
+    pub proceeds: f64,
+    pub cost_basis: f64,
+    pub gain: f64,
+}
+
+impl CurrencyGains {
+    pub fn add(&mut self, sell_trade: &SellTrade) {
+        self.volume += sell_trade.volume;
+        self.proceeds += sell_trade.proceeds;
+        self.cost_basis += sell_trade.cost_basis;
+        self.gain += sell_trade.gain();
+    }
+}
+
+/// Gains of one term per currency, highest proceeds first, and their totals.
+#[derive(Clone, Default, Serialize)]
+pub struct TermGains {
+    pub currencies: Vec<CurrencyGains>,
+    pub total: CurrencyGains,
+}
+
+impl TermGains {
+    /// Sorts the currencies by proceeds, highest first, and sums them up.
+    pub fn new(mut currencies: Vec<CurrencyGains>) -> Self {
+        let mut total = CurrencyGains::default();
+        for gains in &currencies {
+            total.volume += gains.volume;
+            total.proceeds += gains.proceeds;
+            total.cost_basis += gains.cost_basis;
+            total.gain += gains.gain;
+        }
+        currencies.sort_by(|g_1, g_2| g_2.proceeds.partial_cmp(&g_1.proceeds).unwrap());
+        TermGains { currencies, total }
+    }
+}
+
+/// Realized gains of a period split by term.
+#[derive(Clone, Serialize)]
+pub struct PeriodGains {
+    pub name: String,
+    #[serde(with = "serde_datetime")]
+    pub dt_from: DateTime<Tz>,
+    #[serde(with = "serde_datetime")]
+    pub dt_to: DateTime<Tz>,
+    pub long_term: TermGains,
+    pub short_term: TermGains,
+}
+
+/// Lots held of a currency and their totals.
+#[derive(Clone, Serialize)]
+pub struct CurrencyHoldings {
+    pub currency: Currency,
+    pub volume: f64,
+    pub cost_basis: f64,
+    /// May differ from the average price because cost basis includes fees.
+    pub avg_cost: f64,
+    pub lots: Vec<HoldingsItem>,
+}
+
+impl CurrencyHoldings {
+    pub fn new(currency: &str, lots: Vec<HoldingsItem>) -> Self {
+        let mut volume = 0.0;
+        let mut cost_basis = 0.0;
+        for lot in &lots {
+            volume += lot.volume;
+            cost_basis += lot.cost_basis;
+        }
+        CurrencyHoldings {
+            currency: currency.to_owned(),
+            volume,
+            cost_basis,
+            avg_cost: cost_basis / volume,
+            lots,
+        }
+    }
+}
+
+/// Sell trade with the trades it comes from, `buy` being None for lots received in forks.
+#[derive(Clone)]
+pub struct SellTradeDetails<'a> {
+    pub sell_trade: &'a SellTrade,
+    pub sell: &'a Trade,
+    pub buy: Option<&'a Trade>,
+}
+
+/// Fork within the period with the volume received and the income recognized.
+#[derive(Clone, Serialize)]
+pub struct ForkIncome {
+    #[serde(with = "serde_datetime")]
+    pub datetime: DateTime<Tz>,
+    pub currency_from: Currency,
+    pub currency_to: Currency,
+    pub ratio: f64,
+    pub volume: f64,
+    pub price: f64,
+    pub income: f64,
+}
+
+/// Disposal of foreign cash, exempt if the currency gain of its trade is within the threshold.
+#[derive(Clone, Serialize)]
+pub struct FxGain<'a> {
+    #[serde(flatten)]
+    pub sell_trade: &'a SellTrade,
+    pub exempt: bool,
+}
+
+/// Short-term and long-term gains.
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct GainsByTerm {
+    pub short_term: f64,
+    pub long_term: f64,
+}
+
+impl GainsByTerm {
+    pub fn total(&self) -> f64 {
+        self.short_term + self.long_term
+    }
+}
+
+/// Realized gains of the period with all stablecoins under one policy, or the difference
+/// between the policies.
+#[derive(Clone, Serialize)]
+pub struct PolicyGains {
+    pub label: String,
+    pub gains: GainsByTerm,
+}
+
+/// Realized gains of the period with all configured stablecoins treated as cash and as property.
+#[derive(Clone, Serialize)]
+pub struct StablecoinComparison {
+    pub stablecoins: Vec<Currency>,
+    pub policies: Vec<PolicyGains>,
+}
+
+/// Volume held of a currency and its market value, None if the price is unknown.
+#[derive(Clone, Serialize)]
+pub struct CurrencyValue {
+    pub currency: Currency,
+    pub volume: f64,
+    pub price: Option<f64>,
+    pub value: Option<f64>,
+}
+
+/// Lot with its market value and the gain its sale would realize.
+#[derive(Clone, Serialize)]
+pub struct UnrealizedLot {
+    #[serde(flatten)]
+    pub lot: HoldingsItem,
+    pub value: f64,
+    pub gain: f64,
+    pub long_term: bool,
+}
+
+/// Unrealized gains of the lots of a currency, without lots if the price is unknown.
+#[derive(Clone, Serialize)]
+pub struct CurrencyUnrealizedGains {
+    pub currency: Currency,
+    pub volume: f64,
+    pub price: Option<f64>,
+    pub value: f64,
+    pub cost_basis: f64,
+    pub gains: GainsByTerm,
+    pub lots: Vec<UnrealizedLot>,
+}
+
+/// Lot with the time its sale becomes long-term, and the gain at the price known if any.
+#[derive(Clone, Serialize)]
+pub struct LongTermLot {
+    pub currency: Currency,
+    #[serde(flatten)]
+    pub lot: HoldingsItem,
+    #[serde(with = "serde_datetime")]
+    pub dt_long_term: DateTime<Tz>,
+    pub days_left: i64,
+    pub gain: Option<f64>,
+}
+
+/// Lot whose sale would realize a loss.
+#[derive(Clone, Serialize)]
+pub struct HarvestableLoss {
+    pub currency: Currency,
+    #[serde(flatten)]
+    pub lot: HoldingsItem,
+    pub price: f64,
+    pub loss: f64,
+    pub long_term: bool,
+}
+
+/// Lots to sell at a loss, largest loss first, and gains realized since the start of the year.
+#[derive(Clone, Serialize)]
+pub struct TaxLossHarvesting {
+    pub losses: Vec<HarvestableLoss>,
+    pub realized: GainsByTerm,
+    pub harvestable: GainsByTerm,
+}
+
+impl TaxLossHarvesting {
+    /// Gains realized since the start of the year if all the losses were harvested too.
+    pub fn after_harvesting(&self) -> GainsByTerm {
+        GainsByTerm {
+            short_term: self.realized.short_term + self.harvestable.short_term,
+            long_term: self.realized.long_term + self.harvestable.long_term,
+        }
+    }
+}
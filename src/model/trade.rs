@@ -1,35 +1,85 @@
 use std::collections::HashMap;
 use std::fmt;
-use std::fs::File;
-use std::io::BufReader;
 
 use chrono::DateTime;
 use chrono_tz::Tz;
 use lazy_static::lazy_static;
 use rand::{seq::SliceRandom, Rng};
 use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::{
     model::Currency,
-    utils::{
-        read_all_lines,
-        time_utils::{datetime_from_str, datetime_to_str, generate_random_datetime},
+    utils::time_utils::{
+        datetime_from_str, datetime_to_str, generate_random_datetime, serde_datetime,
     },
 };
 
+/// Provenance of a trade: the raw record it was read from, to trace figures back to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    pub file: String,
+    /// Line number starting from 1, None for JSON arrays.
+    pub line: Option<usize>,
+    /// Loader or tool that read the record, e.g. `text`, `json` or `jsonl`.
+    #[serde(default)]
+    pub importer: String,
+    /// Id of the record in the original exchange export, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<String>,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        match (self.importer.is_empty(), &self.row_id) {
+            (true, None) => Ok(()),
+            (false, None) => write!(f, " ({})", self.importer),
+            (true, Some(row_id)) => write!(f, " (row {})", row_id),
+            (false, Some(row_id)) => write!(f, " ({}, row {})", self.importer, row_id),
+        }
+    }
+}
+
+/// Exchange of `volume_from` of one currency for `volume_to` of another, with the prices of both
+/// in the base currency at the time.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Trade {
+    #[serde(with = "serde_datetime")]
     pub datetime: DateTime<Tz>,
     pub exchange_name: String,
     pub volume_from: f64,
     pub currency_from: Currency,
+    #[serde(default = "missing_price", deserialize_with = "deserialize_price")]
     pub currency_from_price_usd: f64,
     pub volume_to: f64,
     pub currency_to: Currency,
+    #[serde(default = "missing_price", deserialize_with = "deserialize_price")]
     pub currency_to_price_usd: f64,
+    #[serde(default)]
     pub notes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
 }
 
 impl Trade {
+    /// Time, exchange, currencies and volumes of the trade, identifying it across imports.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "trade|{}|{}|{:.9}|{}|{:.9}|{}",
+            self.datetime.timestamp_millis(),
+            self.exchange_name,
+            self.volume_from,
+            self.currency_from,
+            self.volume_to,
+            self.currency_to
+        )
+    }
+
+    /// Fees as the value lost in the exchange at the given prices.
     pub fn fees_usd(&self) -> f64 {
         let expected_volume_to =
             (self.volume_from * self.currency_from_price_usd) / self.currency_to_price_usd;
@@ -44,7 +94,8 @@ impl Trade {
         diff_volume * 100.0 / expected_volume_to
     }
 
-    pub fn parse(line: &str) -> Self {
+    /// Parses a trade formatted as by `Display`, with a datetime without offset in `tz`.
+    pub fn parse(line: &str, tz: &Tz) -> Result<Self, String> {
         lazy_static! {
             static ref RE: Regex = Regex::new(concat!(
                 r"^\[(?P<datetime>.+)\] ",
@@ -57,20 +108,22 @@ impl Trade {
             .unwrap();
         }
 
-        let caps = RE.captures(line).unwrap();
-        let datetime = datetime_from_str(&caps["datetime"]).unwrap();
-        let volume_from: f64 = caps["volume_from"].parse().unwrap();
+        let caps = RE
+            .captures(line)
+            .ok_or_else(|| format!("Unknown event format: '{}'", line))?;
+        let datetime = datetime_from_str(&caps["datetime"], tz).map_err(|err| err.to_string())?;
+        let volume_from = parse_number(&caps["volume_from"])?;
         let currency_from = caps["currency_from"].to_string();
-        let volume_to: f64 = caps["volume_to"].parse().unwrap();
+        let volume_to = parse_number(&caps["volume_to"])?;
         let currency_to = caps["currency_to"].to_string();
-        assert_eq!(currency_from, &caps["currency_from_2"]);
-        let currency_from_price_usd: f64 = caps["currency_from_price_usd"].parse().unwrap();
-        assert_eq!(currency_to, &caps["currency_to_2"]);
-        let currency_to_price_usd: f64 = caps["currency_to_price_usd"].parse().unwrap();
+        check_price_currency(&currency_from, &caps["currency_from_2"])?;
+        let currency_from_price_usd = parse_price(&caps["currency_from_price_usd"])?;
+        check_price_currency(&currency_to, &caps["currency_to_2"])?;
+        let currency_to_price_usd = parse_price(&caps["currency_to_price_usd"])?;
         let exchange_name = caps["exchange_name"].to_string();
         let notes = vec![];
 
-        Self {
+        Ok(Self {
             datetime,
             exchange_name,
             volume_from,
@@ -80,10 +133,45 @@ impl Trade {
             currency_to,
             currency_to_price_usd,
             notes,
-        }
+            source: None,
+        })
+    }
+}
+
+/// Missing prices are NaN until filled from a `PriceStore`.
+pub fn missing_price() -> f64 {
+    f64::NAN
+}
+
+/// Parses a price, where `?` stands for a missing one.
+pub fn parse_price(s: &str) -> Result<f64, String> {
+    if s == "?" {
+        Ok(missing_price())
+    } else {
+        parse_number(s)
+    }
+}
+
+pub(crate) fn parse_number(s: &str) -> Result<f64, String> {
+    s.parse().map_err(|_| format!("Invalid number: '{}'", s))
+}
+
+/// Fails unless the price of a text event is given for the currency it belongs to.
+pub(crate) fn check_price_currency(currency: &str, price_currency: &str) -> Result<(), String> {
+    if currency == price_currency {
+        Ok(())
+    } else {
+        Err(format!(
+            "Price of {} given for {}",
+            price_currency, currency
+        ))
     }
 }
 
+pub fn deserialize_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or_else(missing_price))
+}
+
 impl fmt::Display for Trade {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -107,7 +195,7 @@ impl fmt::Debug for Trade {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "[{}] {:.9} {} => {:.9} {} ({}={:.9}, {}={:.9}, {}, fees=[{:.9} USD, {:.9}%])",
+            "[{}] {:.9} {} => {:.9} {} ({}={:.9}, {}={:.9}, {}, fees=[{:.9}, {:.9}%])",
             datetime_to_str(&self.datetime),
             self.volume_from,
             self.currency_from,
@@ -124,27 +212,6 @@ impl fmt::Debug for Trade {
     }
 }
 
-pub fn load_trades(filename: &str) -> Vec<Trade> {
-    let reader = BufReader::new(File::open(filename).unwrap());
-    let lines = read_all_lines(reader).unwrap();
-    let mut trades = vec![];
-
-    let mut i = 0;
-    while i < lines.len() {
-        let mut t = Trade::parse(&lines[i]);
-        i += 1;
-        while i < lines.len() && lines[i].starts_with("--- ") {
-            let note = &lines[i][4..];
-            t.notes.push(note.to_string());
-            i += 1;
-        }
-        trades.push(t);
-    }
-
-    trades
-}
-
-#[allow(dead_code)]
 pub fn generate_random_consistent_trades(
     n: usize,
     dt_from: &DateTime<Tz>,
@@ -165,7 +232,7 @@ pub fn generate_random_consistent_trades(
     let mut wallet: HashMap<Currency, f64> = HashMap::new();
     let mut trades: Vec<Trade> = Vec::new();
 
-    for i in 0..n {
+    for datetime in datetimes {
         let mut can_sell: Vec<String> = Vec::new();
         for (currency, &volume) in wallet.iter() {
             if volume > EPS {
@@ -220,7 +287,6 @@ pub fn generate_random_consistent_trades(
         let volume_to_cur = wallet.entry(currency_to.to_owned()).or_insert(0.0);
         *volume_to_cur += volume_to;
 
-        let datetime = datetimes[i];
         let exchange_name = (*EXCHANGES.choose(&mut rng).unwrap()).to_owned();
         let currency_from = currency_from.to_owned();
         let currency_to = currency_to.to_owned();
@@ -236,6 +302,7 @@ pub fn generate_random_consistent_trades(
             currency_to,
             currency_to_price_usd,
             notes,
+            source: None,
         });
     }
 
@@ -1,29 +1,98 @@
 use std::fmt;
+use std::io;
+use std::str::FromStr;
 
 use chrono::DateTime;
 use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 
-use crate::model::{trade::Trade, Currency};
+use crate::{
+    model::{
+        trade::{Source, Trade},
+        Currency, DEFAULT_BASE_CURRENCY,
+    },
+    utils::time_utils::serde_datetime,
+};
 
 pub const BITCOINTAX_INPUT_COLUMNS: &str = "Date,Action,Source,Symbol,Volume,Price,Currency,Fee";
 const BITCOINTAX_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
 
+const KOINLY_INPUT_COLUMNS: &[&str] = &[
+    "Date",
+    "Sent Amount",
+    "Sent Currency",
+    "Received Amount",
+    "Received Currency",
+    "Fee Amount",
+    "Fee Currency",
+    "Net Worth Amount",
+    "Net Worth Currency",
+    "Label",
+    "Description",
+    "TxHash",
+];
+const KOINLY_TIME_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+const COINTRACKER_INPUT_COLUMNS: &[&str] = &[
+    "Date",
+    "Received Quantity",
+    "Received Currency",
+    "Sent Quantity",
+    "Sent Currency",
+    "Fee Amount",
+    "Fee Currency",
+    "Tag",
+];
+const COINTRACKER_TIME_FORMAT: &str = "%m/%d/%Y %H:%M:%S";
+
+/// Column layout of the exported USD (base currency) trades.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsdTradesFormat {
+    BitcoinTax,
+    Koinly,
+    CoinTracker,
+}
+
+impl FromStr for UsdTradesFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bitcointax" | "bitcoin.tax" => Ok(Self::BitcoinTax),
+            "koinly" => Ok(Self::Koinly),
+            "cointracker" => Ok(Self::CoinTracker),
+            _ => Err(format!("Unknown USD trades format: '{}'", s)),
+        }
+    }
+}
+
 /// USD trade used as input for bitcoin.tax platform.
 /// This approach treats crypto-to-crypto trades as pairs of crypto-USD and USD-crypto ones.
 /// TODO: investigate further how precise it is when exchanges take fees in coins but not in USD.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UsdTrade {
+    #[serde(with = "serde_datetime")]
     pub datetime: DateTime<Tz>,
-    is_buy: bool,
-    exchange_name: String,
-    currency: Currency,
-    volume: f64,
-    price_usd: f64,
-    fees_usd: f64,
+    /// Buy of `currency` for the base currency, else sell.
+    pub is_buy: bool,
+    pub exchange_name: String,
+    pub currency: Currency,
+    pub volume: f64,
+    pub price_usd: f64,
+    pub fees_usd: f64,
+    #[serde(default = "default_base_currency")]
+    pub base_currency: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+fn default_base_currency() -> Currency {
+    DEFAULT_BASE_CURRENCY.to_owned()
 }
 
 impl UsdTrade {
-    pub fn new(trade: &Trade, is_buy: bool) -> Self {
-        let datetime = trade.datetime.clone();
+    pub fn new(trade: &Trade, is_buy: bool, base_currency: &str, is_sold_for_cash: bool) -> Self {
+        let datetime = trade.datetime;
         let exchange_name = trade.exchange_name.clone();
         let currency;
         let volume;
@@ -39,8 +108,8 @@ impl UsdTrade {
             currency = trade.currency_from.clone();
             volume = trade.volume_from;
             price_usd = trade.currency_from_price_usd;
-            // Fees are included in the cost basis of currency_to if not USD.
-            fees_usd = if trade.currency_to == "USD" {
+            // Fees are included in the cost basis of currency_to if not cash.
+            fees_usd = if is_sold_for_cash {
                 trade.fees_usd()
             } else {
                 0.0
@@ -55,10 +124,109 @@ impl UsdTrade {
             volume,
             price_usd,
             fees_usd,
+            base_currency: base_currency.to_owned(),
+            source: trade.source.clone(),
         }
     }
 }
 
+impl UsdTrade {
+    fn to_record(&self, format: UsdTradesFormat) -> Vec<String> {
+        let volume = format!("{:.9}", self.volume);
+        let volume_usd = format!("{:.9}", self.volume * self.price_usd);
+        let fees_usd = format!("{:.9}", self.fees_usd);
+        let (sent, sent_currency, received, received_currency) = if self.is_buy {
+            (
+                volume_usd,
+                self.base_currency.clone(),
+                volume,
+                self.currency.clone(),
+            )
+        } else {
+            (
+                volume,
+                self.currency.clone(),
+                volume_usd,
+                self.base_currency.clone(),
+            )
+        };
+
+        match format {
+            UsdTradesFormat::BitcoinTax => vec![
+                self.datetime.format(BITCOINTAX_TIME_FORMAT).to_string(),
+                (if self.is_buy { "BUY" } else { "SELL" }).to_owned(),
+                self.exchange_name.clone(),
+                self.currency.clone(),
+                format!("{:.9}", self.volume),
+                format!("{:.9}", self.price_usd),
+                self.base_currency.clone(),
+                fees_usd,
+            ],
+            UsdTradesFormat::Koinly => vec![
+                self.datetime
+                    .naive_utc()
+                    .format(KOINLY_TIME_FORMAT)
+                    .to_string(),
+                sent,
+                sent_currency,
+                received,
+                received_currency,
+                fees_usd,
+                self.base_currency.clone(),
+                String::new(),
+                String::new(),
+                String::new(),
+                match &self.source {
+                    Some(source) => format!("{} - {}", self.exchange_name, source),
+                    None => self.exchange_name.clone(),
+                },
+                self.source
+                    .as_ref()
+                    .and_then(|source| source.row_id.clone())
+                    .unwrap_or_default(),
+            ],
+            UsdTradesFormat::CoinTracker => vec![
+                self.datetime
+                    .naive_utc()
+                    .format(COINTRACKER_TIME_FORMAT)
+                    .to_string(),
+                received,
+                received_currency,
+                sent,
+                sent_currency,
+                fees_usd,
+                self.base_currency.clone(),
+                String::new(),
+            ],
+        }
+    }
+}
+
+/// Writes USD trades as CSV in the given layout, header included.
+pub fn write_usd_trades<'a, W, I>(
+    writer: W,
+    usd_trades: I,
+    format: UsdTradesFormat,
+) -> csv::Result<()>
+where
+    W: io::Write,
+    I: IntoIterator<Item = &'a UsdTrade>,
+{
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    match format {
+        UsdTradesFormat::BitcoinTax => {
+            csv_writer.write_record(BITCOINTAX_INPUT_COLUMNS.split(','))?
+        }
+        UsdTradesFormat::Koinly => csv_writer.write_record(KOINLY_INPUT_COLUMNS)?,
+        UsdTradesFormat::CoinTracker => csv_writer.write_record(COINTRACKER_INPUT_COLUMNS)?,
+    }
+    for usd_trade in usd_trades {
+        csv_writer.write_record(usd_trade.to_record(format))?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
 /// Display information as per bitcoin.tax input format.
 impl fmt::Display for UsdTrade {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -66,14 +234,92 @@ impl fmt::Display for UsdTrade {
         let action = if self.is_buy { "BUY" } else { "SELL" };
         write!(
             f,
-            "{},{},{},{},{:.9},{:.9},USD,{:.9}",
+            "{},{},{},{},{:.9},{:.9},{},{:.9}",
             dt,
             action,
             self.exchange_name,
             self.currency,
             self.volume,
             self.price_usd,
+            self.base_currency,
             self.fees_usd,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+
+    const EXCHANGE_NAME: &str = "Big, \"Best\" Exchange";
+
+    fn usd_trade() -> UsdTrade {
+        let trade = Trade {
+            datetime: UTC.ymd(2020, 1, 2).and_hms(3, 4, 5),
+            exchange_name: EXCHANGE_NAME.to_owned(),
+            volume_from: 7070.0,
+            currency_from: "USD".to_owned(),
+            currency_from_price_usd: 1.0,
+            volume_to: 1.0,
+            currency_to: "BTC".to_owned(),
+            currency_to_price_usd: 7000.0,
+            notes: vec![],
+            source: Some(Source {
+                file: "exports/trades, 2020.csv".to_owned(),
+                line: Some(2),
+                importer: "text".to_owned(),
+                row_id: Some("tx \"1\"".to_owned()),
+            }),
+        };
+        UsdTrade::new(&trade, true, "USD", false)
+    }
+
+    fn write_and_read(format: UsdTradesFormat) -> (csv::StringRecord, Vec<csv::StringRecord>) {
+        let mut data = vec![];
+        write_usd_trades(&mut data, &[usd_trade()], format).unwrap();
+        let mut reader = csv::Reader::from_reader(data.as_slice());
+        let headers = reader.headers().unwrap().clone();
+        let records = reader.records().map(|record| record.unwrap()).collect();
+        (headers, records)
+    }
+
+    #[test]
+    fn bitcointax_escapes_exchange_name() {
+        let (headers, records) = write_and_read(UsdTradesFormat::BitcoinTax);
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>().join(","),
+            BITCOINTAX_INPUT_COLUMNS
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0][1], "BUY");
+        assert_eq!(&records[0][2], EXCHANGE_NAME);
+        assert_eq!(&records[0][7], "70.000000000");
+    }
+
+    #[test]
+    fn koinly_escapes_description_and_row_id() {
+        let (headers, records) = write_and_read(UsdTradesFormat::Koinly);
+        assert_eq!(headers.len(), KOINLY_INPUT_COLUMNS.len());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].len(), KOINLY_INPUT_COLUMNS.len());
+        assert_eq!(
+            &records[0][10],
+            "Big, \"Best\" Exchange - exports/trades, 2020.csv:2 (text, row tx \"1\")"
+        );
+        assert_eq!(&records[0][11], "tx \"1\"");
+    }
+
+    #[test]
+    fn cointracker_puts_received_first() {
+        let (headers, records) = write_and_read(UsdTradesFormat::CoinTracker);
+        assert_eq!(headers.len(), COINTRACKER_INPUT_COLUMNS.len());
+        assert_eq!(&records[0][0], "01/02/2020 03:04:05");
+        assert_eq!(&records[0][1], "1.000000000");
+        assert_eq!(&records[0][2], "BTC");
+        assert_eq!(&records[0][3], "7000.000000000");
+        assert_eq!(&records[0][4], "USD");
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::{
+    model::{
+        duplicates::find_duplicates,
+        event::{event_location, Event},
+        price_store::PriceStore,
+        trade::Source,
+        wallet::{Wallet, WalletConfig},
+        Currency,
+    },
+    utils::time_utils::datetime_to_str,
+};
+
+/// Problem found in an event that would make the wallet panic or report wrong numbers.
+pub struct Problem {
+    /// Position of the event in the input, starting from 1.
+    pub event_no: usize,
+    pub datetime: DateTime<Tz>,
+    /// Where the event was read from, shown instead of its position if known.
+    pub source: Option<Source>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}]: {}",
+            event_location(self.event_no, self.source.as_ref()),
+            datetime_to_str(&self.datetime),
+            self.message
+        )
+    }
+}
+
+/// Whether the number is greater than zero, false for NaN.
+fn is_positive(x: f64) -> bool {
+    x > 0.0
+}
+
+/// Checks that events are chronological, have positive volumes and known prices, never sell more
+/// of a currency than held and are not duplicate trades, without adding them to a wallet.
+pub fn validate_events(
+    events: &[Event],
+    config: &WalletConfig,
+    price_store: &PriceStore,
+) -> Vec<Problem> {
+    const EPS: f64 = 1e-5;
+
+    // Only used to tell cash from currencies held as lots, including tracked foreign cash.
+    let wallet = Wallet::new(config.clone());
+    let mut balances: HashMap<Currency, f64> = HashMap::new();
+    let mut last_dt: Option<DateTime<Tz>> = None;
+    let mut problems = vec![];
+
+    for (i, event) in events.iter().enumerate() {
+        let datetime = match event {
+            Event::Trade(t) => t.datetime,
+            Event::Migration(m) => m.datetime,
+            Event::Fork(f) => f.datetime,
+        };
+        let mut problem = |message: String| {
+            problems.push(Problem {
+                event_no: i + 1,
+                datetime,
+                source: event.source().cloned(),
+                message,
+            })
+        };
+
+        if last_dt.is_some_and(|dt| datetime < dt) {
+            problem("not in chronological order".to_owned());
+        }
+        last_dt = Some(datetime);
+
+        match event {
+            Event::Trade(trade) => {
+                if trade.currency_from == trade.currency_to {
+                    problem(format!("trades {} for itself", trade.currency_from));
+                }
+                for (volume, currency, price) in &[
+                    (
+                        trade.volume_from,
+                        &trade.currency_from,
+                        trade.currency_from_price_usd,
+                    ),
+                    (
+                        trade.volume_to,
+                        &trade.currency_to,
+                        trade.currency_to_price_usd,
+                    ),
+                ] {
+                    if !is_positive(*volume) {
+                        problem(format!("volume of {} is not positive", currency));
+                    }
+                    if price.is_nan() {
+                        if price_store.price(currency, &datetime).is_none() {
+                            problem(format!("price of {} is missing and not known", currency));
+                        }
+                    } else if !is_positive(*price) {
+                        problem(format!("price of {} is not positive", currency));
+                    }
+                }
+                if wallet.has_lots(&trade.currency_from) {
+                    let balance = balances.entry(trade.currency_from.clone()).or_default();
+                    if trade.volume_from > *balance + EPS {
+                        problem(format!(
+                            "sells {:.9} {} but only {:.9} is held",
+                            trade.volume_from, trade.currency_from, balance
+                        ));
+                    }
+                    *balance -= trade.volume_from;
+                }
+                if wallet.has_lots(&trade.currency_to) {
+                    *balances.entry(trade.currency_to.clone()).or_default() += trade.volume_to;
+                }
+            }
+            Event::Migration(migration) => {
+                if !is_positive(migration.ratio) {
+                    problem("migration ratio is not positive".to_owned());
+                }
+                let balance = balances.remove(&migration.currency_from).unwrap_or(0.0);
+                *balances.entry(migration.currency_to.clone()).or_default() +=
+                    balance * migration.ratio;
+            }
+            Event::Fork(fork) => {
+                if !is_positive(fork.ratio) {
+                    problem("fork ratio is not positive".to_owned());
+                }
+                if fork.currency_to_price_usd.is_nan()
+                    && price_store.price(&fork.currency_to, &datetime).is_none()
+                {
+                    problem(format!(
+                        "price of {} is missing and not known",
+                        fork.currency_to
+                    ));
+                }
+                let balance = balances.get(&fork.currency_from).copied().unwrap_or(0.0);
+                *balances.entry(fork.currency_to.clone()).or_default() += balance * fork.ratio;
+            }
+        }
+    }
+
+    for duplicate in find_duplicates(events) {
+        problems.push(Problem {
+            event_no: duplicate.event_no,
+            datetime: duplicate.datetime,
+            message: format!(
+                "duplicates {}",
+                event_location(duplicate.original_no, duplicate.original_source.as_ref())
+            ),
+            source: duplicate.source,
+        });
+    }
+    problems.sort_by_key(|problem| problem.event_no);
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::{price_store::PriceLookup, trade::Trade};
+
+    fn trade(
+        day: u32,
+        volume_from: f64,
+        currency_from: &str,
+        volume_to: f64,
+        currency_to: &str,
+    ) -> Event {
+        Event::Trade(Trade {
+            datetime: UTC.ymd(2020, 1, day).and_hms(0, 0, 0),
+            exchange_name: "Exchange".to_owned(),
+            volume_from,
+            currency_from: currency_from.to_owned(),
+            currency_from_price_usd: 1.0,
+            volume_to,
+            currency_to: currency_to.to_owned(),
+            currency_to_price_usd: 1.0,
+            notes: vec![],
+            source: None,
+        })
+    }
+
+    #[test]
+    fn tracked_foreign_cash_shortfall_is_reported() {
+        let config = WalletConfig {
+            cash_currencies: vec!["EUR".to_owned()].into_iter().collect(),
+            track_fx_gains: true,
+            ..WalletConfig::default()
+        };
+        let events = vec![
+            trade(1, 1000.0, "EUR", 0.1, "BTC"),
+            trade(2, 0.1, "BTC", 1100.0, "EUR"),
+        ];
+        let price_store = PriceStore::new(PriceLookup::Nearest, "USD");
+
+        let problems = validate_events(&events, &config, &price_store);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].event_no, 1);
+        assert!(problems[0].message.starts_with("sells 1000.000000000 EUR"));
+
+        let config = WalletConfig {
+            track_fx_gains: false,
+            ..config
+        };
+        assert!(validate_events(&events, &config, &price_store).is_empty());
+    }
+}
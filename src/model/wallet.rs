@@ -1,292 +1,1269 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::str::FromStr;
 
-use chrono::{DateTime, Datelike, TimeZone};
+use chrono::{DateTime, Datelike, NaiveDate};
 use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     model::{
+        event::{Event, Fork, Migration},
+        ledger::{write_ledger, LedgerFormat},
+        period::PeriodScheme,
+        price_store::PriceStore,
+        render::{
+            render, GainsReport, HoldingsReport, OutputFormat, SellTradesReport, TradesReport,
+        },
         sell_trade::SellTrade,
-        trade::Trade,
-        usd_trade::{UsdTrade, BITCOINTAX_INPUT_COLUMNS},
-        Currency,
+        summary::{
+            CurrencyGains, CurrencyHoldings, CurrencyUnrealizedGains, CurrencyValue, ForkIncome,
+            FxGain, GainsByTerm, HarvestableLoss, LongTermLot, PeriodGains, PolicyGains,
+            SellTradeDetails, StablecoinComparison, TaxLossHarvesting, TermGains, UnrealizedLot,
+        },
+        trade::{Source, Trade},
+        usd_trade::{write_usd_trades, UsdTrade, UsdTradesFormat},
+        what_if::{simulate_sale, WhatIfResult, WhatIfSale},
+        Currency, DEFAULT_BASE_CURRENCY,
+    },
+    utils::time_utils::{
+        datetime_to_str, is_datetime_within_limits, long_term_datetime, serde_datetime,
+        start_of_date,
     },
-    utils::time_utils::{datetime_to_str, is_datetime_within_limits, APP_TZ},
 };
 
-struct HoldingsItem {
-    volume: f64,
-    cost_basis: f64,
+/// Sells `currency_from` of the trade from the lots picked by the lot method and returns the
+/// sell trades. Volume beyond the lots held is left unmatched, e.g. foreign cash held before the
+/// first trade of it; `validate_events` reports it.
+fn sell_from_holdings(
+    holdings: &mut HashMap<Currency, Vec<HoldingsItem>>,
+    trade: &Trade,
     trade_idx: usize,
+    is_sold_for_cash: bool,
+    lot_method: LotMethod,
+) -> Vec<SellTrade> {
+    const EPS: f64 = 1e-5;
+
+    let holdings_bucket = holdings.entry(trade.currency_from.clone()).or_default();
+    let mut volume_to_sell = trade.volume_from;
+    let mut sell_trades = vec![];
+
+    while volume_to_sell > EPS && !holdings_bucket.is_empty() {
+        let idx = lot_method.next_lot(holdings_bucket);
+        let lot = holdings_bucket[idx].clone();
+        let volume_sold: f64;
+        let cost_basis_sold: f64;
+
+        if holdings_bucket[idx].volume > volume_to_sell + EPS {
+            volume_sold = volume_to_sell;
+            cost_basis_sold =
+                holdings_bucket[idx].cost_basis * (volume_to_sell / holdings_bucket[idx].volume);
+            holdings_bucket[idx].volume -= volume_sold;
+            holdings_bucket[idx].cost_basis -= cost_basis_sold;
+        } else {
+            volume_sold = holdings_bucket[idx].volume;
+            cost_basis_sold = holdings_bucket[idx].cost_basis;
+            holdings_bucket.remove(idx);
+        }
+
+        sell_trades.push(SellTrade::new(
+            volume_sold,
+            cost_basis_sold,
+            &lot,
+            trade,
+            trade_idx,
+            is_sold_for_cash,
+        ));
+
+        volume_to_sell -= volume_sold;
+    }
+
+    sell_trades
 }
 
-#[derive(Default)]
+/// Adds `currency_to` of the trade as a new lot.
+fn add_to_holdings(
+    holdings: &mut HashMap<Currency, Vec<HoldingsItem>>,
+    trade: &Trade,
+    trade_idx: usize,
+    fees_in_proceeds: bool,
+) {
+    let holdings_bucket = holdings.entry(trade.currency_to.to_owned()).or_default();
+    let mut cost_basis = trade.volume_to * trade.currency_to_price_usd;
+    if !fees_in_proceeds {
+        cost_basis += trade.fees_usd();
+    }
+    holdings_bucket.push(HoldingsItem {
+        volume: trade.volume_to,
+        cost_basis,
+        price_usd: trade.currency_to_price_usd,
+        datetime: trade.datetime,
+        trade_idx: Some(trade_idx),
+        source: trade.source.clone(),
+    });
+}
+
+/// Lot of a currency still held, bought by the trade at `trade_idx` or received in a fork.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HoldingsItem {
+    pub volume: f64,
+    pub cost_basis: f64,
+    /// Price per unit at acquisition, adjusted by migrations.
+    pub price_usd: f64,
+    #[serde(with = "serde_datetime")]
+    pub datetime: DateTime<Tz>,
+    pub trade_idx: Option<usize>, // None if acquired by a fork
+    /// Source of the buy trade.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+impl HoldingsItem {
+    /// Whether selling the lot at the given time would be long-term.
+    pub fn is_long_term_at(&self, dt: &DateTime<Tz>) -> bool {
+        *dt >= long_term_datetime(&self.datetime)
+    }
+}
+
+/// Order in which lots are matched to a sale.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum LotMethod {
+    /// First in, first out: oldest lots first.
+    Fifo,
+    /// Last in, first out: newest lots first.
+    Lifo,
+    /// Highest in, first out: lots with the highest cost basis per unit first.
+    Hifo,
+}
+
+impl FromStr for LotMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(Self::Fifo),
+            "lifo" => Ok(Self::Lifo),
+            "hifo" => Ok(Self::Hifo),
+            _ => Err(format!("Unknown lot method: '{}'", s)),
+        }
+    }
+}
+
+impl LotMethod {
+    /// Index of the next lot to sell from non-empty lots ordered by acquisition.
+    fn next_lot(&self, holdings_bucket: &[HoldingsItem]) -> usize {
+        match self {
+            Self::Fifo => 0,
+            Self::Lifo => holdings_bucket.len() - 1,
+            Self::Hifo => {
+                let unit_cost =
+                    |i: usize| holdings_bucket[i].cost_basis / holdings_bucket[i].volume;
+                (0..holdings_bucket.len())
+                    .max_by(|&i, &j| unit_cost(i).partial_cmp(&unit_cost(j)).unwrap())
+                    .unwrap()
+            }
+        }
+    }
+}
+
+/// Migration applied to the wallet, with the lots of `currency_from` it converted.
+#[derive(Clone)]
+pub struct MigrationRecord {
+    pub migration: Migration,
+    pub lots: Vec<HoldingsItem>,
+}
+
+/// Fork applied to the wallet, with the lot of `currency_to` it created.
+#[derive(Clone)]
+pub struct ForkRecord {
+    pub fork: Fork,
+    pub lot: Option<HoldingsItem>, // None if nothing of `currency_from` was held
+}
+
+/// Cost basis of the lots received in a fork.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForkBasis {
+    /// No cost basis: the whole proceeds are gains when disposed of.
+    Zero,
+    /// Ordinary income at the fair market value of the fork, which becomes the cost basis.
+    Income,
+}
+
+impl FromStr for ForkBasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zero" => Ok(Self::Zero),
+            "income" => Ok(Self::Income),
+            _ => Err(format!("Unknown fork basis: '{}'", s)),
+        }
+    }
+}
+
+/// Position of an event in the list of its kind, to replay events in the order they were added.
+#[derive(Clone, Copy)]
+enum EventIdx {
+    Trade(usize),
+    Migration(usize),
+    Fork(usize),
+}
+
+/// Tax treatment of a stablecoin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StablecoinPolicy {
+    /// Cash equivalent: no lots and no sell trades.
+    Cash,
+    /// Capital asset like any other crypto currency.
+    Property,
+}
+
+impl FromStr for StablecoinPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cash" => Ok(Self::Cash),
+            "property" => Ok(Self::Property),
+            _ => Err(format!("Unknown stablecoin policy: '{}'", s)),
+        }
+    }
+}
+
+/// Treatment of currencies by the wallet.
+#[derive(Clone)]
+pub struct WalletConfig {
+    /// Fiat currency that prices, cost basis and gains are expressed in.
+    pub base_currency: Currency,
+    /// Other fiat currencies held as cash rather than as lots of a capital asset.
+    pub cash_currencies: HashSet<Currency>,
+    /// Whether lots of foreign cash are tracked to report currency gains separately.
+    pub track_fx_gains: bool,
+    /// Currency gains of a single trade up to this amount are exempt as personal use.
+    pub fx_exemption_threshold: f64,
+    /// Treatment of stablecoins; ones not listed are property.
+    pub stablecoins: HashMap<Currency, StablecoinPolicy>,
+    /// Cost basis of the lots received in forks.
+    pub fork_basis: ForkBasis,
+}
+
+impl Default for WalletConfig {
+    fn default() -> Self {
+        Self {
+            base_currency: DEFAULT_BASE_CURRENCY.to_owned(),
+            cash_currencies: HashSet::new(),
+            track_fx_gains: false,
+            fx_exemption_threshold: 0.0,
+            stablecoins: HashMap::new(),
+            fork_basis: ForkBasis::Zero,
+        }
+    }
+}
+
+/// Event added after a newer one.
+#[derive(Debug)]
+pub struct OrderError {
+    pub datetime: DateTime<Tz>,
+    /// Time of the newest event added before.
+    pub last_datetime: DateTime<Tz>,
+    /// Source of the event for messages, if known.
+    pub location: Option<String>,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(
+            f,
+            "event at {} is older than the one at {} before it",
+            datetime_to_str(&self.datetime),
+            datetime_to_str(&self.last_datetime)
+        )
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+/// Lots held and gains realized by the events added so far, in chronological order.
+#[derive(Clone)]
 pub struct Wallet {
     pub trades: Vec<Trade>,
+    /// Method the lots sold by each trade were matched with, to replay it the same way.
+    lot_methods: Vec<LotMethod>,
     sell_trades: Vec<SellTrade>,
     usd_trades: Vec<UsdTrade>,
     holdings: HashMap<Currency, Vec<HoldingsItem>>,
+    fx_sell_trades: Vec<SellTrade>,
+    fx_holdings: HashMap<Currency, Vec<HoldingsItem>>,
+    migrations: Vec<MigrationRecord>,
+    forks: Vec<ForkRecord>,
+    events: Vec<EventIdx>,
+    config: WalletConfig,
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new(WalletConfig::default())
+    }
 }
 
 impl Wallet {
-    pub fn add_trade(&mut self, trade: Trade) {
-        if !self.trades.is_empty() {
-            assert!(
-                self.trades.last().unwrap().datetime <= trade.datetime,
-                "Add trades in chronological order"
-            );
+    pub fn new(config: WalletConfig) -> Self {
+        Self {
+            trades: vec![],
+            lot_methods: vec![],
+            sell_trades: vec![],
+            usd_trades: vec![],
+            holdings: HashMap::new(),
+            fx_sell_trades: vec![],
+            fx_holdings: HashMap::new(),
+            migrations: vec![],
+            forks: vec![],
+            events: vec![],
+            config,
         }
-        const EPS: f64 = 1e-5;
-
-        if trade.currency_from != "USD" {
-            self.usd_trades.push(UsdTrade::new(&trade, false));
-
-            let holdings_bucket = self.holdings.get_mut(&trade.currency_from).unwrap();
-            let mut volume_to_sell = trade.volume_from;
-
-            while volume_to_sell > EPS {
-                let buy_trade_idx = holdings_bucket[0].trade_idx;
-                let buy_trade = &self.trades[buy_trade_idx];
-                assert_eq!(trade.currency_from, buy_trade.currency_to);
-
-                let volume_sold: f64;
-                let cost_basis_sold: f64;
-
-                if holdings_bucket[0].volume > volume_to_sell + EPS {
-                    volume_sold = volume_to_sell;
-                    cost_basis_sold = holdings_bucket[0].cost_basis
-                        * (volume_to_sell / holdings_bucket[0].volume);
-                    holdings_bucket[0].volume -= volume_sold;
-                    holdings_bucket[0].cost_basis -= cost_basis_sold;
-                } else {
-                    volume_sold = holdings_bucket[0].volume;
-                    cost_basis_sold = holdings_bucket[0].cost_basis;
-                    holdings_bucket.remove(0);
-                }
-
-                self.sell_trades.push(SellTrade::new(
-                    volume_sold,
-                    cost_basis_sold,
-                    buy_trade,
-                    &trade,
-                    buy_trade_idx,
-                    self.trades.len(),
-                ));
+    }
 
-                volume_to_sell -= volume_sold;
+    /// Adds a trade, migration or fork, or fails if it is older than the events added before.
+    pub fn add_event(&mut self, event: Event) -> Result<(), OrderError> {
+        if let Some(last_datetime) = self.last_datetime() {
+            if event.datetime() < last_datetime {
+                return Err(OrderError {
+                    datetime: *event.datetime(),
+                    last_datetime: *last_datetime,
+                    location: event.source().map(|source| source.to_string()),
+                });
             }
         }
+        match event {
+            Event::Trade(trade) => self.add_trade(trade),
+            Event::Migration(migration) => self.add_migration(migration),
+            Event::Fork(fork) => self.add_fork(fork),
+        }
+        Ok(())
+    }
 
-        if trade.currency_to != "USD" {
-            self.usd_trades.push(UsdTrade::new(&trade, true));
+    fn last_datetime(&self) -> Option<&DateTime<Tz>> {
+        let last_trade_dt = self.trades.last().map(|t| &t.datetime);
+        let last_migration_dt = self.migrations.last().map(|m| &m.migration.datetime);
+        let last_fork_dt = self.forks.last().map(|f| &f.fork.datetime);
+        last_trade_dt.max(last_migration_dt).max(last_fork_dt)
+    }
 
-            let holdings_bucket = self
-                .holdings
-                .entry(trade.currency_to.to_owned())
-                .or_insert(Vec::new());
-            let cost_basis = (trade.volume_to * trade.currency_to_price_usd) + trade.fees_usd();
+    fn assert_chronological(&self, dt: &DateTime<Tz>) {
+        if let Some(last_dt) = self.last_datetime() {
+            assert!(last_dt <= dt, "Add events in chronological order");
+        }
+    }
+
+    /// Converts all lots of the old currency into the new one, keeping cost basis and
+    /// acquisition dates.
+    pub fn add_migration(&mut self, migration: Migration) {
+        self.assert_chronological(&migration.datetime);
+
+        let lots = self
+            .holdings
+            .remove(&migration.currency_from)
+            .unwrap_or_default();
+        let holdings_bucket = self
+            .holdings
+            .entry(migration.currency_to.clone())
+            .or_default();
+        for lot in &lots {
             holdings_bucket.push(HoldingsItem {
-                volume: trade.volume_to,
-                cost_basis,
-                trade_idx: self.trades.len(),
+                volume: lot.volume * migration.ratio,
+                price_usd: lot.price_usd / migration.ratio,
+                ..lot.clone()
             });
         }
+        // Keep lots ordered by acquisition for the FIFO matching.
+        holdings_bucket.sort_by_key(|lot| lot.datetime);
+
+        self.events.push(EventIdx::Migration(self.migrations.len()));
+        self.migrations.push(MigrationRecord { migration, lots });
+    }
+
+    /// Adds a lot of the forked currency for the holdings of the parent one, with zero cost
+    /// basis or the fair market value as per `WalletConfig::fork_basis`.
+    pub fn add_fork(&mut self, fork: Fork) {
+        self.assert_chronological(&fork.datetime);
+
+        let volume_from: f64 = self
+            .holdings
+            .get(&fork.currency_from)
+            .map_or(0.0, |bucket| bucket.iter().map(|lot| lot.volume).sum());
+        let volume = volume_from * fork.ratio;
+        let lot = if volume > 0.0 {
+            let cost_basis = match self.config.fork_basis {
+                ForkBasis::Zero => 0.0,
+                ForkBasis::Income => volume * fork.currency_to_price_usd,
+            };
+            let lot = HoldingsItem {
+                volume,
+                cost_basis,
+                price_usd: fork.currency_to_price_usd,
+                datetime: fork.datetime,
+                trade_idx: None,
+                source: None,
+            };
+            let holdings_bucket = self.holdings.entry(fork.currency_to.clone()).or_default();
+            holdings_bucket.push(lot.clone());
+            holdings_bucket.sort_by_key(|lot| lot.datetime);
+            Some(lot)
+        } else {
+            None
+        };
+
+        self.events.push(EventIdx::Fork(self.forks.len()));
+        self.forks.push(ForkRecord { fork, lot });
+    }
+
+    /// Sells lots of `currency_from` FIFO unless cash, and adds a lot of `currency_to`.
+    pub fn add_trade(&mut self, trade: Trade) {
+        self.add_trade_with_lot_method(trade, LotMethod::Fifo);
+    }
+
+    /// Adds the trade, matching sold lots with the given method instead of FIFO.
+    pub fn add_trade_with_lot_method(&mut self, trade: Trade, lot_method: LotMethod) {
+        self.assert_chronological(&trade.datetime);
+        let trade_idx = self.trades.len();
+        let base_currency = &self.config.base_currency;
+        let is_sold_for_cash = self.is_cash(&trade.currency_to);
+        // Fees reduce proceeds when selling lots for cash, otherwise they add to the cost basis.
+        let fees_in_proceeds = is_sold_for_cash && self.has_lots(&trade.currency_from);
+
+        if !self.is_cash(&trade.currency_from) {
+            self.usd_trades.push(UsdTrade::new(
+                &trade,
+                false,
+                base_currency,
+                is_sold_for_cash,
+            ));
+            let sell_trades = sell_from_holdings(
+                &mut self.holdings,
+                &trade,
+                trade_idx,
+                is_sold_for_cash,
+                lot_method,
+            );
+            self.sell_trades.extend(sell_trades);
+        } else if self.has_lots(&trade.currency_from) {
+            let fx_sell_trades = sell_from_holdings(
+                &mut self.fx_holdings,
+                &trade,
+                trade_idx,
+                is_sold_for_cash,
+                lot_method,
+            );
+            self.fx_sell_trades.extend(fx_sell_trades);
+        }
+
+        if !is_sold_for_cash {
+            self.usd_trades
+                .push(UsdTrade::new(&trade, true, base_currency, is_sold_for_cash));
+            add_to_holdings(&mut self.holdings, &trade, trade_idx, fees_in_proceeds);
+        } else if self.has_lots(&trade.currency_to) {
+            add_to_holdings(&mut self.fx_holdings, &trade, trade_idx, fees_in_proceeds);
+        }
 
+        self.events.push(EventIdx::Trade(trade_idx));
         self.trades.push(trade);
+        self.lot_methods.push(lot_method);
     }
 
-    pub fn print_trades(&self, print_notes: bool, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
-        for trade in &self.trades {
-            if !is_datetime_within_limits(&trade.datetime, dt_from, dt_to) {
-                continue;
+    pub fn config(&self) -> &WalletConfig {
+        &self.config
+    }
+
+    pub fn base_currency(&self) -> &str {
+        &self.config.base_currency
+    }
+
+    /// Whether the currency is fiat held as cash, i.e. not a capital asset.
+    pub fn is_cash(&self, currency: &str) -> bool {
+        currency == self.config.base_currency
+            || self.config.cash_currencies.contains(currency)
+            || self.config.stablecoins.get(currency) == Some(&StablecoinPolicy::Cash)
+    }
+
+    /// Whether the currency is held as lots: capital assets, and foreign cash if tracked.
+    pub fn has_lots(&self, currency: &str) -> bool {
+        !self.is_cash(currency)
+            || (self.config.track_fx_gains && self.config.cash_currencies.contains(currency))
+    }
+
+    /// Builds a wallet with the same events but a different configuration.
+    pub fn replay(&self, config: WalletConfig) -> Wallet {
+        self.replay_until(config, None, |_, _| {})
+    }
+
+    /// Builds the wallet as it was at the given time, with the events up to it.
+    pub fn at(&self, dt: &DateTime<Tz>) -> Wallet {
+        self.replay_until(self.config.clone(), Some(dt), |_, _| {})
+    }
+
+    /// Replays the events into a new wallet, passing it with the time of the event after each one.
+    pub fn replay_each<F: FnMut(&DateTime<Tz>, &Wallet)>(&self, on_event: F) {
+        self.replay_until(self.config.clone(), None, on_event);
+    }
+
+    fn replay_until<F: FnMut(&DateTime<Tz>, &Wallet)>(
+        &self,
+        config: WalletConfig,
+        dt: Option<&DateTime<Tz>>,
+        mut on_event: F,
+    ) -> Wallet {
+        let mut wallet = Wallet::new(config);
+        for event in &self.events {
+            let event_dt = match *event {
+                EventIdx::Trade(i) => &self.trades[i].datetime,
+                EventIdx::Migration(i) => &self.migrations[i].migration.datetime,
+                EventIdx::Fork(i) => &self.forks[i].fork.datetime,
+            };
+            if dt.is_some_and(|dt| event_dt > dt) {
+                break;
             }
-            println!("{}", trade);
-            if print_notes {
-                for note in &trade.notes {
-                    println!("--- {}", note);
+            match *event {
+                EventIdx::Trade(i) => {
+                    wallet.add_trade_with_lot_method(self.trades[i].clone(), self.lot_methods[i])
                 }
+                EventIdx::Migration(i) => {
+                    wallet.add_migration(self.migrations[i].migration.clone())
+                }
+                EventIdx::Fork(i) => wallet.add_fork(self.forks[i].fork.clone()),
             }
+            on_event(event_dt, &wallet);
         }
+        wallet
     }
 
-    pub fn print_usd_trades(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
-        println!("{}", BITCOINTAX_INPUT_COLUMNS);
-        for usd_trade in &self.usd_trades {
-            if !is_datetime_within_limits(&usd_trade.datetime, dt_from, dt_to) {
+    /// Short-term and long-term realized gains of the period.
+    pub fn realized_gains(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) -> (f64, f64) {
+        let mut gain_st = 0.0;
+        let mut gain_lt = 0.0;
+        for st in &self.sell_trades {
+            if !is_datetime_within_limits(&st.sell_datetime, dt_from, dt_to) {
                 continue;
             }
-            println!("{}", usd_trade);
+            if st.is_long_term() {
+                gain_lt += st.gain();
+            } else {
+                gain_st += st.gain();
+            }
         }
+        (gain_st, gain_lt)
     }
 
-    pub fn print_sell_trades(&self, full_info: bool, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
-        for (i, sell_trade) in (&self.sell_trades).iter().enumerate() {
-            if !is_datetime_within_limits(&sell_trade.sell_datetime, dt_from, dt_to) {
-                continue;
-            }
-            println!("{}", sell_trade);
-            if full_info {
-                let st = &self.trades[sell_trade.sell_trade_idx];
-                println!("- SELL: {}", st);
-                for note in &st.notes {
-                    println!("--- {}", note);
-                }
-                let bt = &self.trades[sell_trade.buy_trade_idx];
-                println!("- BUY: {}", bt);
-                for note in &bt.notes {
-                    println!("--- {}", note);
-                }
-                if i + 1 < self.sell_trades.len() {
-                    println!();
-                }
-            }
-        }
+    /// Lots sold so far, one sell trade per lot and trade.
+    pub fn sell_trades(&self) -> &[SellTrade] {
+        &self.sell_trades
     }
 
-    pub fn print_proceeds(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
-        self.print_proceeds_for_period(dt_from, dt_to, "Target period");
-
-        for year in dt_from.year()..=dt_to.year() {
-            // Time bounds as per US quarterly estimated tax due dates.
-            let dt_bounds = [
-                APP_TZ.ymd(year, 1, 1).and_hms(0, 0, 0),
-                APP_TZ.ymd(year, 4, 1).and_hms(0, 0, 0),
-                APP_TZ.ymd(year, 6, 1).and_hms(0, 0, 0),
-                APP_TZ.ymd(year, 9, 1).and_hms(0, 0, 0),
-                APP_TZ.ymd(year + 1, 1, 1).and_hms(0, 0, 0),
-            ];
-            for quarter in 0..dt_bounds.len() - 1 {
-                let dt_l = std::cmp::max(dt_from, &dt_bounds[quarter]);
-                let dt_r = std::cmp::min(dt_to, &dt_bounds[quarter + 1]);
-                if dt_r < dt_l {
-                    continue;
-                }
-                println!();
+    pub fn migrations(&self) -> &[MigrationRecord] {
+        &self.migrations
+    }
 
-                let period_name = format!("{} Q{}", year, quarter + 1);
-                self.print_proceeds_for_period(dt_l, dt_r, &period_name);
-            }
-        }
+    pub fn forks(&self) -> &[ForkRecord] {
+        &self.forks
+    }
+
+    /// Lots of foreign cash sold so far, if tracked.
+    pub fn fx_sell_trades(&self) -> &[SellTrade] {
+        &self.fx_sell_trades
+    }
+
+    /// Trades split into buys and sells against the base currency.
+    pub fn usd_trades(&self) -> &[UsdTrade] {
+        &self.usd_trades
+    }
+
+    /// Lots still held per currency, oldest first.
+    pub fn holdings(&self) -> &HashMap<Currency, Vec<HoldingsItem>> {
+        &self.holdings
     }
 
-    fn print_proceeds_for_period(
+    /// Trades within the time boundaries.
+    pub fn trades_within(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) -> Vec<&Trade> {
+        self.trades
+            .iter()
+            .filter(|trade| is_datetime_within_limits(&trade.datetime, dt_from, dt_to))
+            .collect()
+    }
+
+    pub fn print_trades(&self, print_notes: bool, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
+        let report = TradesReport {
+            trades: self.trades_within(dt_from, dt_to),
+            print_notes,
+        };
+        render(&report, OutputFormat::Text, &mut io::stdout()).unwrap();
+    }
+
+    /// Prints USD trades within the time boundaries as CSV in the given layout.
+    pub fn print_usd_trades(
         &self,
+        format: UsdTradesFormat,
         dt_from: &DateTime<Tz>,
         dt_to: &DateTime<Tz>,
-        period_name: &str,
     ) {
-        #[derive(Default)]
-        struct ProceedsInfo {
-            volume: f64,
-            proceeds: f64,
-            cost_basis: f64,
-            gain: f64,
+        let usd_trades = self.usd_trades_within_limits(dt_from, dt_to);
+        write_usd_trades(io::stdout(), usd_trades, format).unwrap();
+    }
+
+    /// Writes USD trades within the time boundaries to a CSV file.
+    pub fn export_usd_trades(
+        &self,
+        filename: &str,
+        format: UsdTradesFormat,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+    ) -> csv::Result<()> {
+        let file = File::create(filename)?;
+        let usd_trades = self.usd_trades_within_limits(dt_from, dt_to);
+        write_usd_trades(file, usd_trades, format)
+    }
+
+    /// Simulates the sale on a copy of the wallet, see `what_if::simulate_sale`.
+    pub fn simulate_sale(&self, sale: &WhatIfSale) -> Result<WhatIfResult, String> {
+        simulate_sale(self, sale)
+    }
+
+    /// Writes all trades to a Beancount or ledger-cli journal file.
+    pub fn export_ledger(&self, filename: &str, format: LedgerFormat) -> io::Result<()> {
+        let file = File::create(filename)?;
+        write_ledger(BufWriter::new(file), self, format)
+    }
+
+    fn usd_trades_within_limits<'a>(
+        &'a self,
+        dt_from: &'a DateTime<Tz>,
+        dt_to: &'a DateTime<Tz>,
+    ) -> impl Iterator<Item = &'a UsdTrade> {
+        self.usd_trades
+            .iter()
+            .filter(move |t| is_datetime_within_limits(&t.datetime, dt_from, dt_to))
+    }
+
+    /// Sell trades within the time boundaries with the trades they come from.
+    pub fn sell_trade_details(
+        &self,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+    ) -> Vec<SellTradeDetails<'_>> {
+        self.sell_trades
+            .iter()
+            .filter(|st| is_datetime_within_limits(&st.sell_datetime, dt_from, dt_to))
+            .map(|sell_trade| SellTradeDetails {
+                sell_trade,
+                sell: &self.trades[sell_trade.sell_trade_idx],
+                buy: sell_trade.buy_trade_idx.map(|i| &self.trades[i]),
+            })
+            .collect()
+    }
+
+    pub fn print_sell_trades(&self, full_info: bool, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) {
+        let report = SellTradesReport {
+            sell_trades: self.sell_trade_details(dt_from, dt_to),
+            full_info,
+        };
+        render(&report, OutputFormat::Text, &mut io::stdout()).unwrap();
+    }
+
+    /// Realized gains of the target period, then of each of its periods as per the scheme.
+    pub fn gains_per_period(
+        &self,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+        period_scheme: PeriodScheme,
+    ) -> Vec<PeriodGains> {
+        let mut gains = vec![self.gains_for_period("Target period", dt_from, dt_to)];
+        for period in period_scheme.periods(dt_from, dt_to) {
+            gains.push(self.gains_for_period(&period.name, &period.dt_from, &period.dt_to));
         }
-        let mut proceeds_lt = HashMap::<Currency, ProceedsInfo>::new();
-        let mut proceeds_st = HashMap::<Currency, ProceedsInfo>::new();
+        gains
+    }
+
+    /// Realized gains within the time boundaries per term and currency.
+    pub fn gains_for_period(
+        &self,
+        name: &str,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+    ) -> PeriodGains {
+        let mut gains_lt = HashMap::<Currency, CurrencyGains>::new();
+        let mut gains_st = HashMap::<Currency, CurrencyGains>::new();
 
         for st in &self.sell_trades {
             if !is_datetime_within_limits(&st.sell_datetime, dt_from, dt_to) {
                 continue;
             }
-            let infos = if st.is_long_term() {
-                &mut proceeds_lt
+            let gains = if st.is_long_term() {
+                &mut gains_lt
             } else {
-                &mut proceeds_st
+                &mut gains_st
             };
-            let entry = infos
+            gains
                 .entry(st.currency.clone())
-                .or_insert(ProceedsInfo::default());
-            entry.volume += st.volume;
-            entry.proceeds += st.proceeds;
-            entry.cost_basis += st.cost_basis;
-            entry.gain += st.gain();
+                .or_insert_with(|| CurrencyGains {
+                    currency: Some(st.currency.clone()),
+                    ..CurrencyGains::default()
+                })
+                .add(st);
         }
 
-        for &is_lt in &[true, false] {
-            let (proceeds, label) = if is_lt {
-                (&proceeds_lt, "LONG")
-            } else {
-                (&proceeds_st, "SHORT")
-            };
-            let mut totals = ProceedsInfo::default();
-            let mut currencies = vec![];
-            for (currency, info) in proceeds {
-                currencies.push(currency);
-                totals.volume += info.volume;
-                totals.proceeds += info.proceeds;
-                totals.cost_basis += info.cost_basis;
-                totals.gain += info.gain;
-            }
+        PeriodGains {
+            name: name.to_owned(),
+            dt_from: *dt_from,
+            dt_to: *dt_to,
+            long_term: TermGains::new(gains_lt.into_values().collect()),
+            short_term: TermGains::new(gains_st.into_values().collect()),
+        }
+    }
 
-            currencies.sort_by(|c_1, c_2| {
-                let info_1 = proceeds.get(*c_1).unwrap();
-                let info_2 = proceeds.get(*c_2).unwrap();
-                info_2.proceeds.partial_cmp(&info_1.proceeds).unwrap()
-            });
+    /// Prints gains of the target period, then of each of its periods as per the scheme.
+    pub fn print_proceeds(
+        &self,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+        period_scheme: PeriodScheme,
+    ) {
+        let report = GainsReport {
+            periods: self.gains_per_period(dt_from, dt_to, period_scheme),
+        };
+        render(&report, OutputFormat::Text, &mut io::stdout()).unwrap();
+    }
+
+    /// Forks within the time boundaries with the volume received and the income recognized.
+    pub fn fork_incomes(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) -> Vec<ForkIncome> {
+        self.forks
+            .iter()
+            .filter(|record| is_datetime_within_limits(&record.fork.datetime, dt_from, dt_to))
+            .map(|record| {
+                let fork = &record.fork;
+                let (volume, income) = record
+                    .lot
+                    .as_ref()
+                    .map_or((0.0, 0.0), |lot| (lot.volume, lot.cost_basis));
+                ForkIncome {
+                    datetime: fork.datetime,
+                    currency_from: fork.currency_from.clone(),
+                    currency_to: fork.currency_to.clone(),
+                    ratio: fork.ratio,
+                    volume,
+                    price: fork.currency_to_price_usd,
+                    income,
+                }
+            })
+            .collect()
+    }
 
-            if !is_lt {
-                println!();
+    /// Disposals of foreign cash within the time boundaries, exempt ones marked.
+    pub fn fx_gains(&self, dt_from: &DateTime<Tz>, dt_to: &DateTime<Tz>) -> Vec<FxGain<'_>> {
+        let mut gains_per_trade = HashMap::<usize, f64>::new();
+        for st in &self.fx_sell_trades {
+            *gains_per_trade.entry(st.sell_trade_idx).or_default() += st.gain();
+        }
+        self.fx_sell_trades
+            .iter()
+            .filter(|st| is_datetime_within_limits(&st.sell_datetime, dt_from, dt_to))
+            .map(|sell_trade| FxGain {
+                sell_trade,
+                exempt: gains_per_trade[&sell_trade.sell_trade_idx]
+                    <= self.config.fx_exemption_threshold,
+            })
+            .collect()
+    }
+
+    /// Realized gains within the time boundaries with all configured stablecoins treated as cash
+    /// and as property.
+    pub fn stablecoin_comparison(
+        &self,
+        dt_from: &DateTime<Tz>,
+        dt_to: &DateTime<Tz>,
+    ) -> StablecoinComparison {
+        let mut stablecoins: Vec<Currency> = self.config.stablecoins.keys().cloned().collect();
+        stablecoins.sort();
+        let gains_with_policy = |policy: StablecoinPolicy| {
+            let mut config = self.config.clone();
+            for policy_of in config.stablecoins.values_mut() {
+                *policy_of = policy;
             }
-            println!("{} {}-TERM gains:", period_name, label);
-            for currency in currencies {
-                let info = proceeds.get(currency).unwrap();
-                println!(
-                    "- {}: volume={:.9} proceeds={:.9} cost_basis={:.9} gains={:.9}",
-                    currency, info.volume, info.proceeds, info.cost_basis, info.gain
-                );
+            let (short_term, long_term) = self.replay(config).realized_gains(dt_from, dt_to);
+            GainsByTerm {
+                short_term,
+                long_term,
             }
-            println!(
-                "total_volume={:.9} total_proceeds={:.9} total_cost_basis={:.9} total_gains={:.9}",
-                totals.volume, totals.proceeds, totals.cost_basis, totals.gain
-            );
+        };
+        let cash = gains_with_policy(StablecoinPolicy::Cash);
+        let property = gains_with_policy(StablecoinPolicy::Property);
+        let difference = GainsByTerm {
+            short_term: property.short_term - cash.short_term,
+            long_term: property.long_term - cash.long_term,
+        };
+
+        let policies = [
+            ("as cash", cash),
+            ("as property", property),
+            ("difference (property - cash)", difference),
+        ]
+        .iter()
+        .map(|(label, gains)| PolicyGains {
+            label: label.to_string(),
+            gains: *gains,
+        })
+        .collect();
+        StablecoinComparison {
+            stablecoins,
+            policies,
         }
     }
 
+    /// Lots held per currency, highest cost basis first.
+    pub fn holdings_summary(&self) -> Vec<CurrencyHoldings> {
+        let mut holdings: Vec<CurrencyHoldings> = self
+            .holdings
+            .iter()
+            .map(|(currency, bucket)| CurrencyHoldings::new(currency, bucket.clone()))
+            .collect();
+        holdings.sort_by(|h_1, h_2| h_2.cost_basis.partial_cmp(&h_1.cost_basis).unwrap());
+        holdings
+    }
+
     pub fn print_holdings(&self) {
-        struct BucketInfo {
-            total_volume: f64,
-            total_cost_basis: f64,
-            currency: Currency,
-        }
-        let mut infos: Vec<BucketInfo> = Vec::new();
+        let report = HoldingsReport {
+            holdings: self.holdings_summary(),
+        };
+        render(&report, OutputFormat::Text, &mut io::stdout()).unwrap();
+    }
+
+    /// Unrealized gains of the lots held per currency at the given time, sorted by currency.
+    pub fn unrealized_gains(
+        &self,
+        price_store: &PriceStore,
+        dt: &DateTime<Tz>,
+    ) -> Vec<CurrencyUnrealizedGains> {
+        let mut currencies: Vec<&Currency> = self.holdings.keys().collect();
+        currencies.sort();
+        currencies
+            .into_iter()
+            .map(|currency| {
+                let holdings_bucket = &self.holdings[currency];
+                let volume: f64 = holdings_bucket.iter().map(|item| item.volume).sum();
+                let mut info = CurrencyUnrealizedGains {
+                    currency: currency.clone(),
+                    volume,
+                    price: price_store.valuation_price(currency, dt),
+                    value: 0.0,
+                    cost_basis: 0.0,
+                    gains: GainsByTerm::default(),
+                    lots: vec![],
+                };
+                let price = match info.price {
+                    Some(price) => price,
+                    None => return info,
+                };
+                for item in holdings_bucket {
+                    let value = item.volume * price;
+                    let gain = value - item.cost_basis;
+                    let long_term = item.is_long_term_at(dt);
+                    info.value += value;
+                    info.cost_basis += item.cost_basis;
+                    if long_term {
+                        info.gains.long_term += gain;
+                    } else {
+                        info.gains.short_term += gain;
+                    }
+                    info.lots.push(UnrealizedLot {
+                        lot: item.clone(),
+                        value,
+                        gain,
+                        long_term,
+                    });
+                }
+                info
+            })
+            .collect()
+    }
+
+    /// Every lot with the time its sale becomes long-term and the days remaining from the given
+    /// time, soonest first, with the unrealized gain at the prices known.
+    pub fn long_term_calendar(
+        &self,
+        price_store: &PriceStore,
+        dt: &DateTime<Tz>,
+    ) -> Vec<LongTermLot> {
+        let mut lots: Vec<LongTermLot> = self
+            .holdings
+            .iter()
+            .flat_map(|(currency, bucket)| {
+                let price = price_store.valuation_price(currency, dt);
+                bucket.iter().map(move |item| {
+                    let dt_long_term = long_term_datetime(&item.datetime);
+                    LongTermLot {
+                        currency: currency.clone(),
+                        lot: item.clone(),
+                        dt_long_term,
+                        days_left: std::cmp::max(0, (dt_long_term - *dt).num_days()),
+                        gain: price.map(|price| item.volume * price - item.cost_basis),
+                    }
+                })
+            })
+            .collect();
+        lots.sort_by_key(|lot| lot.dt_long_term);
+        lots
+    }
+
+    /// Lots whose sale at the given time would realize a loss, largest loss first, and the gains
+    /// realized since the start of the year.
+    pub fn tax_loss_harvesting(
+        &self,
+        price_store: &PriceStore,
+        dt: &DateTime<Tz>,
+    ) -> TaxLossHarvesting {
+        let mut losses: Vec<HarvestableLoss> = vec![];
         for (currency, holdings_bucket) in &self.holdings {
-            let mut info = BucketInfo {
-                total_volume: 0.0,
-                total_cost_basis: 0.0,
-                currency: currency.to_owned(),
+            let price = match price_store.valuation_price(currency, dt) {
+                Some(price) => price,
+                None => continue,
             };
             for item in holdings_bucket {
-                info.total_volume += item.volume;
-                info.total_cost_basis += item.cost_basis;
+                let loss = item.volume * price - item.cost_basis;
+                if loss < 0.0 {
+                    losses.push(HarvestableLoss {
+                        currency: currency.clone(),
+                        lot: item.clone(),
+                        price,
+                        loss,
+                        long_term: item.is_long_term_at(dt),
+                    });
+                }
             }
-            infos.push(info);
         }
-        infos.sort_by(|info_1, info_2| {
-            info_2
-                .total_cost_basis
-                .partial_cmp(&info_1.total_cost_basis)
-                .unwrap()
-        });
-        for info in infos {
-            // avg_cost may differ from average price because cost basis includes fees.
-            println!(
-                "{}: volume={:.9}, cost_basis={:.9}, avg_cost={:.9}",
-                info.currency,
-                info.total_volume,
-                info.total_cost_basis,
-                info.total_cost_basis / info.total_volume,
-            );
-            for item in self.holdings.get(&info.currency).unwrap() {
-                let trade = &self.trades[item.trade_idx];
-                println!(
-                    "  - {:.9} {} (cost_basis={:.9}, price={:.9}, {})",
-                    item.volume,
-                    trade.currency_to,
-                    item.cost_basis,
-                    trade.currency_to_price_usd,
-                    datetime_to_str(&trade.datetime),
-                );
+        losses.sort_by(|info_1, info_2| info_1.loss.partial_cmp(&info_2.loss).unwrap());
+
+        let mut harvestable = GainsByTerm::default();
+        for info in &losses {
+            if info.long_term {
+                harvestable.long_term += info.loss;
+            } else {
+                harvestable.short_term += info.loss;
             }
         }
+
+        let dt_year = start_of_date(&dt.timezone(), NaiveDate::from_ymd(dt.year(), 1, 1));
+        // Nothing realized yet at the very start of the year.
+        let (short_term, long_term) = if dt_year < *dt {
+            self.realized_gains(&dt_year, dt)
+        } else {
+            (0.0, 0.0)
+        };
+        TaxLossHarvesting {
+            losses,
+            realized: GainsByTerm {
+                short_term,
+                long_term,
+            },
+            harvestable,
+        }
+    }
+
+    /// Volume and market value of the current holdings per currency at the given time.
+    pub fn holdings_value(
+        &self,
+        price_store: &PriceStore,
+        dt: &DateTime<Tz>,
+    ) -> Vec<CurrencyValue> {
+        let mut currencies: Vec<&Currency> = self.holdings.keys().collect();
+        currencies.sort();
+        currencies
+            .into_iter()
+            .map(|currency| {
+                let volume: f64 = self.holdings[currency].iter().map(|item| item.volume).sum();
+                let price = price_store.valuation_price(currency, dt);
+                CurrencyValue {
+                    currency: currency.clone(),
+                    volume,
+                    price,
+                    value: price.map(|price| volume * price),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::price_store::PriceLookup;
+
+    fn trade(
+        day: u32,
+        volume_from: f64,
+        currency_from: &str,
+        currency_from_price_usd: f64,
+        volume_to: f64,
+        currency_to: &str,
+        currency_to_price_usd: f64,
+    ) -> Trade {
+        Trade {
+            datetime: UTC.ymd(2020, 1, day).and_hms(0, 0, 0),
+            exchange_name: "Exchange".to_owned(),
+            volume_from,
+            currency_from: currency_from.to_owned(),
+            currency_from_price_usd,
+            volume_to,
+            currency_to: currency_to.to_owned(),
+            currency_to_price_usd,
+            notes: vec![],
+            source: None,
+        }
+    }
+
+    fn fx_config() -> WalletConfig {
+        WalletConfig {
+            cash_currencies: vec!["EUR".to_owned()].into_iter().collect(),
+            track_fx_gains: true,
+            ..WalletConfig::default()
+        }
+    }
+
+    #[test]
+    fn foreign_cash_without_lots_is_not_matched() {
+        let mut wallet = Wallet::new(fx_config());
+        wallet.add_trade(trade(1, 1000.0, "EUR", 1.1, 0.1, "BTC", 11000.0));
+        wallet.add_trade(trade(2, 0.1, "BTC", 12000.0, 1100.0, "EUR", 1.1));
+        assert!(wallet.fx_sell_trades().is_empty());
+        assert_eq!(wallet.sell_trades().len(), 1);
+        assert_eq!(wallet.fx_holdings["EUR"].len(), 1);
+    }
+
+    #[test]
+    fn overselling_foreign_cash_matches_the_lots_held() {
+        let mut wallet = Wallet::new(fx_config());
+        wallet.add_trade(trade(1, 110.0, "USD", 1.0, 100.0, "EUR", 1.1));
+        wallet.add_trade_with_lot_method(
+            trade(2, 300.0, "EUR", 1.2, 360.0, "USD", 1.0),
+            LotMethod::Lifo,
+        );
+        let fx_sell_trades = wallet.fx_sell_trades();
+        assert_eq!(fx_sell_trades.len(), 1);
+        assert!((fx_sell_trades[0].volume - 100.0).abs() < 1e-9);
+        assert!((fx_sell_trades[0].gain() - 10.0).abs() < 1e-9);
+        assert!(wallet.fx_holdings["EUR"].is_empty());
+    }
+
+    #[test]
+    fn fx_gains_up_to_the_threshold_are_exempt() {
+        let config = WalletConfig {
+            fx_exemption_threshold: 5.0,
+            ..fx_config()
+        };
+        let mut wallet = Wallet::new(config);
+        wallet.add_trade(trade(1, 110.0, "USD", 1.0, 100.0, "EUR", 1.1));
+        wallet.add_trade(trade(2, 50.0, "EUR", 1.15, 57.5, "USD", 1.0));
+        wallet.add_trade(trade(3, 50.0, "EUR", 1.3, 65.0, "USD", 1.0));
+        let dt_from = UTC.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let dt_to = UTC.ymd(2020, 12, 31).and_hms(0, 0, 0);
+        let fx_gains = wallet.fx_gains(&dt_from, &dt_to);
+        let gains: Vec<(f64, bool)> = fx_gains
+            .iter()
+            .map(|fx_gain| (fx_gain.sell_trade.gain(), fx_gain.exempt))
+            .collect();
+        assert_eq!(gains.len(), 2);
+        assert!((gains[0].0 - 2.5).abs() < 1e-9 && gains[0].1);
+        assert!((gains[1].0 - 10.0).abs() < 1e-9 && !gains[1].1);
+        assert!(wallet.sell_trades().is_empty());
+    }
+
+    #[test]
+    fn stablecoins_as_cash_have_no_lots() {
+        let config = WalletConfig {
+            stablecoins: vec![("USDT".to_owned(), StablecoinPolicy::Cash)]
+                .into_iter()
+                .collect(),
+            ..WalletConfig::default()
+        };
+        let mut wallet = Wallet::new(config);
+        wallet.add_trade(trade(1, 1000.0, "USD", 1.0, 1000.0, "USDT", 1.0));
+        wallet.add_trade(trade(2, 1000.0, "USDT", 1.0, 0.1, "BTC", 10000.0));
+        wallet.add_trade(trade(3, 0.1, "BTC", 12000.0, 1200.0, "USDT", 1.0));
+        wallet.add_trade(trade(4, 1200.0, "USDT", 1.01, 1212.0, "USD", 1.0));
+        assert_eq!(wallet.sell_trades().len(), 1);
+        assert!(!wallet.holdings().contains_key("USDT"));
+
+        let dt_from = UTC.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let dt_to = UTC.ymd(2020, 12, 31).and_hms(0, 0, 0);
+        let comparison = wallet.stablecoin_comparison(&dt_from, &dt_to);
+        assert_eq!(comparison.stablecoins, vec!["USDT".to_owned()]);
+        let short_term: Vec<f64> = comparison
+            .policies
+            .iter()
+            .map(|policy| policy.gains.short_term)
+            .collect();
+        // As property, the 12 USD the last 1200 USDT gained in value are a capital gain too.
+        assert!((short_term[0] - 200.0).abs() < 1e-9);
+        assert!((short_term[1] - 212.0).abs() < 1e-9);
+        assert!((short_term[2] - 12.0).abs() < 1e-9);
+    }
+
+    fn fork_wallet(fork_basis: ForkBasis) -> Wallet {
+        let mut wallet = Wallet::new(WalletConfig {
+            fork_basis,
+            ..WalletConfig::default()
+        });
+        wallet.add_trade(trade(1, 14000.0, "USD", 1.0, 2.0, "BTC", 7000.0));
+        wallet.add_fork(Fork {
+            datetime: UTC.ymd(2020, 1, 2).and_hms(0, 0, 0),
+            currency_from: "BTC".to_owned(),
+            currency_to: "BCH".to_owned(),
+            ratio: 1.0,
+            currency_to_price_usd: 300.0,
+            notes: vec![],
+            source: None,
+        });
+        wallet.add_trade(trade(3, 2.0, "BCH", 400.0, 800.0, "USD", 1.0));
+        wallet
+    }
+
+    #[test]
+    fn fork_income_becomes_the_cost_basis() {
+        let dt_from = UTC.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let dt_to = UTC.ymd(2020, 12, 31).and_hms(0, 0, 0);
+        for (fork_basis, income, gain) in [
+            (ForkBasis::Zero, 0.0, 800.0),
+            (ForkBasis::Income, 600.0, 200.0),
+        ]
+        .iter()
+        {
+            let wallet = fork_wallet(*fork_basis);
+            let fork_incomes = wallet.fork_incomes(&dt_from, &dt_to);
+            assert_eq!(fork_incomes.len(), 1);
+            assert_eq!(fork_incomes[0].volume, 2.0);
+            assert!((fork_incomes[0].income - income).abs() < 1e-9);
+            assert_eq!(wallet.sell_trades().len(), 1);
+            assert_eq!(wallet.sell_trades()[0].buy_trade_idx, None);
+            assert!((wallet.sell_trades()[0].gain() - gain).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn migration_keeps_cost_basis_and_dates() {
+        let mut wallet = Wallet::default();
+        wallet.add_trade(trade(1, 100.0, "USD", 1.0, 10.0, "LEND", 10.0));
+        wallet.add_migration(Migration {
+            datetime: UTC.ymd(2020, 1, 2).and_hms(0, 0, 0),
+            currency_from: "LEND".to_owned(),
+            currency_to: "AAVE".to_owned(),
+            ratio: 0.01,
+            notes: vec![],
+            source: None,
+        });
+        assert!(!wallet.holdings().contains_key("LEND"));
+        let lot = wallet.holdings()["AAVE"][0].clone();
+        assert!((lot.volume - 0.1).abs() < 1e-12);
+        assert_eq!(lot.cost_basis, 100.0);
+        assert_eq!(lot.datetime, UTC.ymd(2020, 1, 1).and_hms(0, 0, 0));
+
+        wallet.add_trade(trade(3, 0.1, "AAVE", 1500.0, 150.0, "USD", 1.0));
+        let sell_trade = &wallet.sell_trades()[0];
+        assert!((sell_trade.gain() - 50.0).abs() < 1e-9);
+        assert_eq!(sell_trade.buy_datetime, lot.datetime);
+    }
+
+    #[test]
+    fn sell_trades_keep_the_sources_of_both_trades() {
+        let source = |line: usize| Source {
+            file: "trades.txt".to_owned(),
+            line: Some(line),
+            importer: "text".to_owned(),
+            row_id: None,
+        };
+        let mut wallet = Wallet::default();
+        wallet.add_trade(Trade {
+            source: Some(source(1)),
+            ..trade(1, 7000.0, "USD", 1.0, 1.0, "BTC", 7000.0)
+        });
+        wallet.add_trade(Trade {
+            source: Some(source(2)),
+            ..trade(2, 1.0, "BTC", 8000.0, 8000.0, "USD", 1.0)
+        });
+        let sell_trade = &wallet.sell_trades()[0];
+        assert_eq!(sell_trade.buy_source, Some(source(1)));
+        assert_eq!(sell_trade.sell_source, Some(source(2)));
+    }
+
+    #[test]
+    fn unrealized_gains_and_harvestable_losses() {
+        let mut wallet = Wallet::default();
+        wallet.add_trade(trade(1, 7000.0, "USD", 1.0, 1.0, "BTC", 7000.0));
+        wallet.add_trade(trade(2, 300.0, "USD", 1.0, 1.0, "ETH", 300.0));
+        wallet.add_trade(trade(3, 0.5, "BTC", 8000.0, 4000.0, "USD", 1.0));
+        let mut price_store = PriceStore::new(PriceLookup::Close, "USD");
+        price_store.set_override("BTC".to_owned(), 9000.0);
+        price_store.set_override("ETH".to_owned(), 200.0);
+        let dt = UTC.ymd(2020, 6, 1).and_hms(0, 0, 0);
+
+        let unrealized = wallet.unrealized_gains(&price_store, &dt);
+        let gains: Vec<(&str, f64)> = unrealized
+            .iter()
+            .map(|info| (info.currency.as_str(), info.gains.short_term))
+            .collect();
+        assert_eq!(gains, vec![("BTC", 1000.0), ("ETH", -100.0)]);
+
+        let harvesting = wallet.tax_loss_harvesting(&price_store, &dt);
+        assert_eq!(harvesting.losses.len(), 1);
+        assert_eq!(harvesting.losses[0].currency, "ETH");
+        assert!((harvesting.realized.short_term - 500.0).abs() < 1e-9);
+        assert!((harvesting.after_harvesting().short_term - 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn events_out_of_order_are_rejected() {
+        let mut wallet = Wallet::default();
+        let event = |day: u32| Event::Trade(trade(day, 7000.0, "USD", 1.0, 1.0, "BTC", 7000.0));
+        wallet.add_event(event(2)).unwrap();
+        wallet.add_event(event(2)).unwrap();
+        let err = wallet.add_event(event(1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "event at 2020-01-01 00:00:00+00:00 is older than the one at 2020-01-02 00:00:00+00:00 before it"
+        );
+        assert_eq!(wallet.trades.len(), 2);
+    }
+
+    #[test]
+    fn replay_keeps_the_lot_method_of_each_trade() {
+        let mut wallet = Wallet::default();
+        wallet.add_trade(trade(1, 5000.0, "USD", 1.0, 1.0, "BTC", 5000.0));
+        wallet.add_trade(trade(2, 9000.0, "USD", 1.0, 1.0, "BTC", 9000.0));
+        wallet.add_trade(trade(3, 7000.0, "USD", 1.0, 1.0, "BTC", 7000.0));
+        wallet.add_trade_with_lot_method(
+            trade(4, 1.0, "BTC", 8000.0, 8000.0, "USD", 1.0),
+            LotMethod::Hifo,
+        );
+        wallet.add_trade_with_lot_method(
+            trade(5, 1.0, "BTC", 8000.0, 8000.0, "USD", 1.0),
+            LotMethod::Lifo,
+        );
+        let cost_bases = |wallet: &Wallet| -> Vec<f64> {
+            wallet
+                .sell_trades()
+                .iter()
+                .map(|st| st.cost_basis)
+                .collect()
+        };
+        assert_eq!(cost_bases(&wallet), vec![9000.0, 7000.0]);
+        assert_eq!(
+            cost_bases(&wallet.replay(wallet.config().clone())),
+            cost_bases(&wallet)
+        );
+        let dt = UTC.ymd(2020, 1, 4).and_hms(12, 0, 0);
+        assert_eq!(cost_bases(&wallet.at(&dt)), vec![9000.0]);
     }
 }
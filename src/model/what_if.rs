@@ -0,0 +1,188 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{
+    model::{
+        sell_trade::SellTrade,
+        trade::Trade,
+        wallet::{LotMethod, Wallet},
+        Currency,
+    },
+    utils::time_utils::{datetime_to_str, serde_datetime},
+};
+
+/// Hypothetical sale of a currency for the base currency.
+#[derive(Clone, Serialize)]
+pub struct WhatIfSale {
+    #[serde(with = "serde_datetime")]
+    pub datetime: DateTime<Tz>,
+    pub volume: f64,
+    pub currency: Currency,
+    pub price_usd: f64,
+    pub lot_method: LotMethod,
+}
+
+impl WhatIfSale {
+    /// Parses a sale formatted as `<volume> <currency> @ <price>` (e.g.: `1.5 BTC @ 40000`).
+    pub fn parse(s: &str, datetime: DateTime<Tz>, lot_method: LotMethod) -> Result<Self, String> {
+        let err = || format!("Invalid sale: '{}'", s);
+        let (amount, price) = s.split_once('@').ok_or_else(err)?;
+        let mut amount = amount.split_whitespace();
+        let volume = amount.next().and_then(|v| v.parse().ok()).ok_or_else(err)?;
+        let currency = amount.next().ok_or_else(err)?.to_owned();
+        let price_usd = price.trim().parse().map_err(|_| err())?;
+        Ok(Self {
+            datetime,
+            volume,
+            currency,
+            price_usd,
+            lot_method,
+        })
+    }
+}
+
+/// Outcome of a simulated sale.
+pub struct WhatIfResult {
+    pub sale: WhatIfSale,
+    pub sell_trades: Vec<SellTrade>,
+    pub gain_st: f64,
+    pub gain_lt: f64,
+    /// Wallet after the sale.
+    pub wallet: Wallet,
+}
+
+/// Runs the sale against the wallet as it was at the time of the sale, which is left untouched.
+/// Fails if less than the volume of the sale was held at that time.
+pub fn simulate_sale(wallet: &Wallet, sale: &WhatIfSale) -> Result<WhatIfResult, String> {
+    let mut wallet = wallet.at(&sale.datetime);
+    let volume_held: f64 = wallet
+        .holdings()
+        .get(&sale.currency)
+        .map_or(0.0, |bucket| bucket.iter().map(|item| item.volume).sum());
+    if sale.volume > volume_held {
+        return Err(format!(
+            "Cannot sell {} {} on {}, only {} held",
+            sale.volume,
+            sale.currency,
+            datetime_to_str(&sale.datetime),
+            volume_held
+        ));
+    }
+
+    let trade = Trade {
+        datetime: sale.datetime,
+        exchange_name: "WhatIf".to_owned(),
+        volume_from: sale.volume,
+        currency_from: sale.currency.clone(),
+        currency_from_price_usd: sale.price_usd,
+        volume_to: sale.volume * sale.price_usd,
+        currency_to: wallet.base_currency().to_owned(),
+        currency_to_price_usd: 1.0,
+        notes: vec![],
+        source: None,
+    };
+    let sell_trades_count = wallet.sell_trades().len();
+    wallet.add_trade_with_lot_method(trade, sale.lot_method);
+
+    let sell_trades = wallet.sell_trades()[sell_trades_count..].to_vec();
+    let mut gain_st = 0.0;
+    let mut gain_lt = 0.0;
+    for st in &sell_trades {
+        if st.is_long_term() {
+            gain_lt += st.gain();
+        } else {
+            gain_st += st.gain();
+        }
+    }
+
+    Ok(WhatIfResult {
+        sale: sale.clone(),
+        sell_trades,
+        gain_st,
+        gain_lt,
+        wallet,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    use super::*;
+    use crate::model::wallet::WalletConfig;
+
+    /// Wallet with lots of 1 BTC bought at 5000, 9000 and 7000.
+    fn wallet() -> Wallet {
+        let mut wallet = Wallet::new(WalletConfig::default());
+        for (month, price) in [(1, 5000.0), (2, 9000.0), (3, 7000.0)].iter() {
+            wallet.add_trade(Trade {
+                datetime: UTC.ymd(2020, *month, 1).and_hms(0, 0, 0),
+                exchange_name: "Exchange".to_owned(),
+                volume_from: *price,
+                currency_from: "USD".to_owned(),
+                currency_from_price_usd: 1.0,
+                volume_to: 1.0,
+                currency_to: "BTC".to_owned(),
+                currency_to_price_usd: *price,
+                notes: vec![],
+                source: None,
+            });
+        }
+        wallet
+    }
+
+    fn sale(s: &str, lot_method: LotMethod) -> WhatIfSale {
+        WhatIfSale::parse(s, UTC.ymd(2020, 6, 1).and_hms(0, 0, 0), lot_method).unwrap()
+    }
+
+    #[test]
+    fn sale_is_matched_with_the_lot_method() {
+        let wallet = wallet();
+        for (lot_method, gain) in [
+            (LotMethod::Fifo, 3000.0),
+            (LotMethod::Lifo, 1000.0),
+            (LotMethod::Hifo, -1000.0),
+        ]
+        .iter()
+        {
+            let result = simulate_sale(&wallet, &sale("1 BTC @ 8000", *lot_method)).unwrap();
+            assert_eq!(result.sell_trades.len(), 1);
+            assert!((result.gain_st - gain).abs() < 1e-9, "{:?}", lot_method);
+            assert_eq!(result.gain_lt, 0.0);
+        }
+        assert!(wallet.sell_trades().is_empty());
+    }
+
+    #[test]
+    fn hifo_sale_spans_the_most_expensive_lots() {
+        let result = simulate_sale(&wallet(), &sale("1.5 BTC @ 8000", LotMethod::Hifo)).unwrap();
+        let cost_bases: Vec<f64> = result.sell_trades.iter().map(|st| st.cost_basis).collect();
+        assert_eq!(cost_bases, vec![9000.0, 3500.0]);
+        assert!((result.gain_st - (12000.0 - 12500.0)).abs() < 1e-9);
+        let lots = &result.wallet.holdings()["BTC"];
+        assert_eq!(lots.len(), 2);
+        assert!((lots.iter().map(|lot| lot.volume).sum::<f64>() - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sale_is_limited_to_the_holdings_at_its_time() {
+        let wallet = wallet();
+        let early_sale = WhatIfSale::parse(
+            "2.5 BTC @ 8000",
+            UTC.ymd(2020, 2, 15).and_hms(0, 0, 0),
+            LotMethod::Fifo,
+        )
+        .unwrap();
+        assert!(simulate_sale(&wallet, &early_sale).is_err());
+        assert!(simulate_sale(&wallet, &sale("2.5 BTC @ 8000", LotMethod::Fifo)).is_ok());
+    }
+
+    #[test]
+    fn invalid_sale_is_rejected() {
+        let dt = UTC.ymd(2020, 6, 1).and_hms(0, 0, 0);
+        assert!(WhatIfSale::parse("1.5 BTC", dt, LotMethod::Fifo).is_err());
+        assert!(WhatIfSale::parse("BTC @ 8000", dt, LotMethod::Fifo).is_err());
+    }
+}
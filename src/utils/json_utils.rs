@@ -0,0 +1,47 @@
+use std::io::{BufRead, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Reads one JSON value per non-empty line (JSON Lines).
+pub fn read_jsonl<T: DeserializeOwned, R: BufRead>(reader: R) -> serde_json::Result<Vec<T>> {
+    let items = read_jsonl_with_lines(reader).map_err(|(_, err)| err)?;
+    Ok(items.into_iter().map(|(_, item)| item).collect())
+}
+
+/// Reads one JSON value per non-empty line (JSON Lines) along with its line number, starting
+/// from 1, or fails with the line of the error.
+pub fn read_jsonl_with_lines<T: DeserializeOwned, R: BufRead>(
+    reader: R,
+) -> Result<Vec<(usize, T)>, (usize, serde_json::Error)> {
+    let mut items = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| (i + 1, serde_json::Error::io(err)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item = serde_json::from_str(&line).map_err(|err| (i + 1, err))?;
+        items.push((i + 1, item));
+    }
+    Ok(items)
+}
+
+/// Writes one JSON value per line (JSON Lines).
+pub fn write_jsonl<'a, T, W, I>(mut writer: W, items: I) -> serde_json::Result<()>
+where
+    T: Serialize + 'a,
+    W: Write,
+    I: IntoIterator<Item = &'a T>,
+{
+    for item in items {
+        serde_json::to_writer(&mut writer, item)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    writer.flush().map_err(serde_json::Error::io)
+}
+
+/// Writes a pretty-printed JSON value followed by a newline.
+pub fn write_json<T: Serialize, W: Write>(mut writer: W, value: &T) -> serde_json::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer).map_err(serde_json::Error::io)?;
+    writer.flush().map_err(serde_json::Error::io)
+}
@@ -1,3 +1,4 @@
+pub mod json_utils;
 pub mod time_utils;
 
 use std::io::BufRead;
@@ -1,13 +1,17 @@
-use chrono::{DateTime, Datelike, TimeZone};
-use chrono_tz::Tz;
+use std::fmt;
 
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone,
+};
+use chrono_tz::Tz;
 use rand::Rng;
 
-pub const APP_TZ: &Tz = &chrono_tz::UTC;
-// pub const APP_TZ: &Tz = &chrono_tz::US::Pacific;
-// pub const APP_TZ: &Tz = &chrono_tz::US::Central;
+/// Timezone that datetimes are parsed into and reported in unless configured otherwise.
+pub const DEFAULT_APP_TZ: Tz = chrono_tz::UTC;
 
 pub const APP_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%:z";
+/// `APP_TIME_FORMAT` with fractions of a second, left out when zero.
+const PRECISE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f%:z";
 
 pub fn generate_random_datetime<R: Rng>(
     dt_from: &DateTime<Tz>,
@@ -19,18 +23,115 @@ pub fn generate_random_datetime<R: Rng>(
     let to_millis = dt_to.timestamp_millis();
     let max_rng = to_millis - from_millis;
     let millis = from_millis + rng.gen_range(0..max_rng);
-    APP_TZ.timestamp_millis(millis)
+    dt_from.timezone().timestamp_millis(millis)
+}
+
+/// Formats with a UTC offset accepted by `datetime_from_str`, besides a trailing `Z`.
+const OFFSET_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%dT%H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+/// Formats without a UTC offset accepted by `datetime_from_str`, interpreted in its timezone.
+const NAIVE_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Epoch timestamps have at least this many digits, i.e. are after March 1973, so that compact
+/// dates such as `20200131` are not taken for them.
+const EPOCH_MIN_DIGITS: usize = 9;
+/// Epoch timestamps with at least this many digits are in milliseconds rather than seconds.
+const EPOCH_MILLIS_DIGITS: usize = 12;
+
+#[derive(Debug)]
+pub enum DatetimeParseError {
+    /// Not in any of the supported formats.
+    InvalidFormat(String),
+    /// Local time occurring twice in the timezone, when clocks are turned back.
+    Ambiguous(String, DateTime<Tz>, DateTime<Tz>),
+    /// Local time skipped in the timezone, when clocks are turned forward.
+    NonExistent(String, Tz),
 }
 
-pub fn datetime_from_str(s: &str) -> Result<DateTime<Tz>, chrono::ParseError> {
-    DateTime::parse_from_str(s, APP_TIME_FORMAT)
-        .map(|dt_fixed_offset| dt_fixed_offset.with_timezone(APP_TZ))
+impl fmt::Display for DatetimeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(s) => write!(f, "Unknown datetime format: '{}'", s),
+            Self::Ambiguous(s, dt_1, dt_2) => write!(
+                f,
+                "Ambiguous local time '{}': either {} or {}",
+                s,
+                datetime_to_str(dt_1),
+                datetime_to_str(dt_2)
+            ),
+            Self::NonExistent(s, tz) => write!(f, "Non-existent local time '{}' in {}", s, tz),
+        }
+    }
+}
+
+impl std::error::Error for DatetimeParseError {}
+
+/// Parses a datetime in `APP_TIME_FORMAT`, ISO 8601 with `T` and `Z` or an offset, Unix epoch
+/// seconds or milliseconds of at least `EPOCH_MIN_DIGITS` digits, or a local time without offset (e.g. `2020-01-31 12:00:00` or
+/// `2020-01-31`), into the timezone.
+pub fn datetime_from_str(s: &str, tz: &Tz) -> Result<DateTime<Tz>, DatetimeParseError> {
+    let tz = *tz;
+    let s = s.trim();
+
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        let invalid = || DatetimeParseError::InvalidFormat(s.to_owned());
+        if s.len() < EPOCH_MIN_DIGITS {
+            return Err(invalid());
+        }
+        let timestamp: i64 = s.parse().map_err(|_| invalid())?;
+        let dt = if s.len() >= EPOCH_MILLIS_DIGITS {
+            tz.timestamp_millis_opt(timestamp)
+        } else {
+            tz.timestamp_opt(timestamp, 0)
+        };
+        return dt.single().ok_or_else(invalid);
+    }
+
+    let s_offset = match s.strip_suffix('Z').or_else(|| s.strip_suffix('z')) {
+        Some(s) => format!("{}+00:00", s),
+        None => s.to_owned(),
+    };
+    for format in OFFSET_TIME_FORMATS {
+        if let Ok(dt) = DateTime::parse_from_str(&s_offset, format) {
+            return Ok(dt.with_timezone(&tz));
+        }
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(start_of_date(&tz, date));
+    }
+    let naive = NAIVE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .ok_or_else(|| DatetimeParseError::InvalidFormat(s.to_owned()))?;
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(dt) => Ok(dt),
+        LocalResult::Ambiguous(dt_1, dt_2) => {
+            Err(DatetimeParseError::Ambiguous(s.to_owned(), dt_1, dt_2))
+        }
+        LocalResult::None => Err(DatetimeParseError::NonExistent(s.to_owned(), tz)),
+    }
 }
 
 pub fn datetime_to_str(dt: &DateTime<Tz>) -> String {
     dt.format(APP_TIME_FORMAT).to_string()
 }
 
+/// Like `datetime_to_str`, with fractions of a second if any, to store datetimes without loss.
+pub fn datetime_to_precise_str(dt: &DateTime<Tz>) -> String {
+    dt.format(PRECISE_TIME_FORMAT).to_string()
+}
+
 pub fn is_datetime_within_limits(
     dt: &DateTime<Tz>,
     dt_from: &DateTime<Tz>,
@@ -40,14 +141,202 @@ pub fn is_datetime_within_limits(
     dt >= dt_from && dt < dt_to
 }
 
+/// First instant of the date in the timezone: midnight, or the end of the gap if clocks are
+/// turned forward at midnight (e.g. `America/Santiago`).
+pub fn start_of_date(tz: &Tz, date: NaiveDate) -> DateTime<Tz> {
+    let midnight = date.and_hms(0, 0, 0);
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(dt) => dt,
+        None => {
+            // The gap starts at midnight in the offset of the day before.
+            let offset = tz
+                .offset_from_utc_datetime(&(midnight - Duration::days(1)))
+                .fix();
+            let utc = midnight - Duration::seconds(offset.local_minus_utc() as i64);
+            tz.from_utc_datetime(&utc)
+        }
+    }
+}
+
+/// Start of the day in the timezone of the datetime.
 pub fn start_of_the_day(dt: &DateTime<Tz>) -> DateTime<Tz> {
-    APP_TZ
-        .ymd(dt.year(), dt.month(), dt.day())
-        .and_hms_milli(0, 0, 0, 0)
+    start_of_date(&dt.timezone(), dt.naive_local().date())
 }
 
+/// End of the day in the timezone of the datetime, the last millisecond before the next one.
 pub fn end_of_the_day(dt: &DateTime<Tz>) -> DateTime<Tz> {
-    APP_TZ
-        .ymd(dt.year(), dt.month(), dt.day())
-        .and_hms_milli(23, 59, 59, 999)
+    start_of_date(&dt.timezone(), dt.naive_local().date().succ()) - Duration::milliseconds(1)
+}
+
+/// First time at which an asset acquired at `dt` counts as held for more than one year.
+pub fn long_term_datetime(dt: &DateTime<Tz>) -> DateTime<Tz> {
+    let mut date = dt.naive_local().date().succ();
+    if date.month() == 2 && date.day() == 29 {
+        // leap_year/02/29 is a special case.
+        date = date.succ();
+    }
+    let date = NaiveDate::from_ymd(date.year() + 1, date.month(), date.day());
+    start_of_date(&dt.timezone(), date)
+}
+
+/// Serializes datetimes using `APP_TIME_FORMAT` with fractions of a second if any, for
+/// `#[serde(with = "...")]`. Deserializes into UTC, as there is no timezone to interpret local
+/// times in; event loaders parse those beforehand.
+pub mod serde_datetime {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::{datetime_from_str, datetime_to_precise_str};
+
+    pub fn serialize<S: Serializer>(dt: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&datetime_to_precise_str(dt))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Tz>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        datetime_from_str(&s, &chrono_tz::UTC).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use chrono_tz::America::Santiago;
+    use chrono_tz::US::Pacific;
+    use chrono_tz::UTC;
+
+    use super::*;
+
+    #[test]
+    fn start_of_date_skipped_midnight() {
+        // Clocks jumped from 00:00 to 01:00 on 2019-09-08.
+        let dt = start_of_date(&Santiago, NaiveDate::from_ymd(2019, 9, 8));
+        assert_eq!(datetime_to_str(&dt), "2019-09-08 01:00:00-03:00");
+        let dt = start_of_date(&Santiago, NaiveDate::from_ymd(2019, 9, 9));
+        assert_eq!(datetime_to_str(&dt), "2019-09-09 00:00:00-03:00");
+    }
+
+    #[test]
+    fn days_around_skipped_midnight() {
+        let dt = Santiago.ymd(2019, 9, 8).and_hms(10, 0, 0);
+        assert_eq!(
+            datetime_to_str(&start_of_the_day(&dt)),
+            "2019-09-08 01:00:00-03:00"
+        );
+        let dt = Santiago.ymd(2019, 9, 7).and_hms(10, 0, 0);
+        assert_eq!(
+            end_of_the_day(&dt).to_rfc3339(),
+            "2019-09-07T23:59:59.999-04:00"
+        );
+    }
+
+    #[test]
+    fn long_term_after_one_year() {
+        let dt = UTC.ymd(2019, 3, 15).and_hms(12, 0, 0);
+        assert_eq!(
+            datetime_to_str(&long_term_datetime(&dt)),
+            "2020-03-16 00:00:00+00:00"
+        );
+        let dt = UTC.ymd(2020, 2, 28).and_hms(12, 0, 0);
+        assert_eq!(
+            datetime_to_str(&long_term_datetime(&dt)),
+            "2021-03-01 00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn parse_epoch() {
+        let dt = datetime_from_str("1577836800", &UTC).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-01-01 00:00:00+00:00");
+        let dt = datetime_from_str("1577836800123", &UTC).unwrap();
+        assert_eq!(
+            datetime_to_precise_str(&dt),
+            "2020-01-01 00:00:00.123+00:00"
+        );
+    }
+
+    #[test]
+    fn parse_epoch_out_of_range() {
+        assert!(matches!(
+            datetime_from_str("99999999999999999", &UTC),
+            Err(DatetimeParseError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            datetime_from_str("99999999999999999999", &UTC),
+            Err(DatetimeParseError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn parse_compact_date_is_not_epoch() {
+        assert!(matches!(
+            datetime_from_str("20200131", &UTC),
+            Err(DatetimeParseError::InvalidFormat(_))
+        ));
+        assert!(datetime_from_str("123456789", &UTC).is_ok());
+    }
+
+    #[test]
+    fn parse_with_offset() {
+        let dt = datetime_from_str("2020-01-31T12:00:00Z", &UTC).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-01-31 12:00:00+00:00");
+        let dt = datetime_from_str("2020-01-31 12:00:00+02:00", &UTC).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-01-31 10:00:00+00:00");
+    }
+
+    #[test]
+    fn parse_local() {
+        let dt = datetime_from_str("2020-01-31 12:00:00", &UTC).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-01-31 12:00:00+00:00");
+        let dt = datetime_from_str("2020-01-31", &UTC).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-01-31 00:00:00+00:00");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(
+            datetime_from_str("2020-13-01", &UTC),
+            Err(DatetimeParseError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            datetime_from_str("yesterday", &UTC),
+            Err(DatetimeParseError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn parse_local_in_timezone() {
+        let dt = datetime_from_str("2020-07-01 12:00:00", &Pacific).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-07-01 12:00:00-07:00");
+        let dt = datetime_from_str("2020-07-01T19:00:00Z", &Pacific).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-07-01 12:00:00-07:00");
+    }
+
+    #[test]
+    fn parse_ambiguous() {
+        // Clocks were turned back from 02:00 to 01:00 on 2020-11-01.
+        match datetime_from_str("2020-11-01 01:30:00", &Pacific) {
+            Err(DatetimeParseError::Ambiguous(_, dt_1, dt_2)) => {
+                assert_eq!(datetime_to_str(&dt_1), "2020-11-01 01:30:00-07:00");
+                assert_eq!(datetime_to_str(&dt_2), "2020-11-01 01:30:00-08:00");
+            }
+            result => panic!("expected an ambiguous time, got {:?}", result),
+        }
+        let dt = datetime_from_str("2020-11-01 01:30:00-08:00", &Pacific).unwrap();
+        assert_eq!(datetime_to_str(&dt), "2020-11-01 01:30:00-08:00");
+    }
+
+    #[test]
+    fn parse_non_existent() {
+        // Clocks were turned forward from 02:00 to 03:00 on 2020-03-08.
+        let err = datetime_from_str("2020-03-08 02:30:00", &Pacific).unwrap_err();
+        assert!(matches!(err, DatetimeParseError::NonExistent(_, _)));
+        assert_eq!(
+            err.to_string(),
+            "Non-existent local time '2020-03-08 02:30:00' in US/Pacific"
+        );
+    }
 }